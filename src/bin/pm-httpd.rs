@@ -1,65 +1,595 @@
 use axum::{
-    extract::Path,
-    http::StatusCode,
+    extract::{Path, Query},
+    http::{header, HeaderName, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Extension, Json, Router,
 };
-use clap::Parser;
-use pmtorrent::{FileDescription, FileRepo, Piece, RepoError};
-use std::{net::SocketAddr, sync::Arc};
-use tokio::fs::File;
+use clap::{Parser, ValueEnum};
+use pmtorrent::{
+    chunk_size_for, AnyHash, BlockPiece, CancellationToken, CdcParams, Compression, Directory,
+    DirectoryPiece, EncryptionKey, ErasureParams, File, FileBuilder, FileDescription, FileError,
+    FileRepo, GrowingFile, HashAlgorithm, IngestPhase, IngestProgress, Manifest, Piece, RangePiece,
+    RepoError, RootAlias, CHUNK_BYTES,
+};
+use ring::signature::Ed25519KeyPair;
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
-    #[clap(value_parser)]
-    path: String,
+    /// A file or a directory to host, every file of a directory is hashed into its own tree.
+    #[clap(value_parser, required_unless_present = "repo")]
+    path: Option<String>,
 
     #[clap(short, long, default_value_t = 8080u16)]
     port: u16,
+
+    /// Hash algorithm for the merkle tree, can be repeated to host the file under several roots.
+    #[clap(long = "hash", default_value = "sha256", value_parser = parse_hash, multiple_occurrences = true)]
+    hashes: Vec<HashAlgorithm>,
+
+    /// Size of a chunk in bytes, by default it is picked from the file size (1024 bytes for the
+    /// files of a directory).
+    #[clap(long, value_parser)]
+    chunk_size: Option<usize>,
+
+    /// Split the file in content-defined chunks with the provided `min,avg,max` sizes in bytes
+    /// instead of fixed size ones.
+    #[clap(long, value_parser = parse_cdc)]
+    cdc: Option<CdcParams>,
+
+    /// Memory map the file and serve chunks without copying them. Only `/piece/.../raw` avoids
+    /// the copy, and truncating the file while it's served crashes the server with SIGBUS.
+    #[clap(long)]
+    mmap: bool,
+
+    /// Keep the chunks of files that are loaded into memory compressed (`none` or `deflate`).
+    #[clap(long, default_value = "none", value_parser = parse_compression)]
+    store_compression: Compression,
+
+    /// Encrypt every chunk and host only the encrypted file, the read capability that is needed
+    /// to decrypt it is printed on startup. The key is derived from the content (`convergent`)
+    /// or generated (`secret`).
+    #[clap(long, value_enum)]
+    encrypt: Option<KeyMode>,
+
+    /// Also host the file erasure coded with the provided `data,parity` chunks per stripe, the
+    /// info that is needed to decode it is printed on startup.
+    #[clap(long, value_parser = parse_erasure)]
+    erasure: Option<ErasureParams>,
+
+    /// Keep reading a file that is still being written and publish a new root every time it
+    /// grows, the new root is added as the next version of the previous one.
+    #[clap(long)]
+    follow: bool,
+
+    /// Number of previous snapshots of a followed file that are still served, all of them are
+    /// kept by default.
+    #[clap(long, value_parser, requires = "follow")]
+    keep_versions: Option<usize>,
+
+    /// Write the hashing state of a large file to `<path>.<hash>.checkpoint` every GiB and
+    /// resume from it if it exists, e.g. after a crash.
+    #[clap(long)]
+    checkpoint: bool,
+
+    /// Print the progress of hashing a file to stderr. Ctrl-C stops hashing in any case.
+    #[clap(long)]
+    progress: bool,
+
+    /// Keep the hosted files in a repo in this directory, they're hosted again on the next
+    /// start without hashing them. The path is optional with a repo.
+    #[clap(long, value_parser)]
+    repo: Option<String>,
+
+    /// Rehash the hosted files with another algorithm, old roots are served as aliases signed
+    /// with `--signing-key`.
+    #[clap(long, value_parser = parse_hash, requires = "signing-key")]
+    migrate_to: Option<HashAlgorithm>,
+
+    /// Path to a PKCS#8 Ed25519 key used to sign the aliases recorded by `--migrate-to`.
+    #[clap(long, value_parser)]
+    signing_key: Option<String>,
+}
+
+/// Bytes hashed between two checkpoints of `--checkpoint`.
+const CHECKPOINT_BYTES: u64 = 1 << 30;
+
+/// Bytes hashed between two lines of `--progress`.
+const PROGRESS_BYTES: u64 = 64 << 20;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum KeyMode {
+    Convergent,
+    Secret,
+}
+
+fn parse_hash(s: &str) -> Result<HashAlgorithm, String> {
+    s.parse().map_err(|_| {
+        let names: Vec<&str> = HashAlgorithm::ALL.iter().map(|a| a.name()).collect();
+        format!("expected one of: {}", names.join(", "))
+    })
+}
+
+fn parse_compression(s: &str) -> Result<Compression, String> {
+    s.parse().map_err(|_| {
+        let names: Vec<&str> = Compression::ALL.iter().map(|c| c.name()).collect();
+        format!("expected one of: {}", names.join(", "))
+    })
+}
+
+fn parse_cdc(s: &str) -> Result<CdcParams, String> {
+    let sizes = s
+        .split(',')
+        .map(|size| size.trim().parse::<usize>())
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    match sizes[..] {
+        [min, avg, max] if 0 < min && min <= avg && avg <= max => Ok(CdcParams::new(min, avg, max)),
+        _ => Err("expected min,avg,max sizes with 0 < min <= avg <= max".to_string()),
+    }
+}
+
+fn parse_erasure(s: &str) -> Result<ErasureParams, String> {
+    match s
+        .split_once(',')
+        .map(|(d, p)| (d.trim().parse(), p.trim().parse()))
+    {
+        Some((Ok(data), Ok(parity))) if data > 0 && parity > 0 && data + parity <= 256 => {
+            Ok(ErasureParams::new(data, parity))
+        }
+        _ => Err("expected data,parity chunk counts with data + parity <= 256".to_string()),
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = Args::parse();
 
-    let file = File::open(args.path).await?;
-    let file = pmtorrent::File::from_reader(file).await.unwrap();
+    let mut repo = match &args.repo {
+        Some(dir) => FileRepo::open(dir, args.store_compression)
+            .map_err(|e| format!("failed to open the repo: {:?}", e))?,
+        None => FileRepo::new(args.store_compression),
+    };
 
-    let mut repo = FileRepo::default();
-    repo.add(file).expect("new file");
+    // The first Ctrl-C stops hashing, once the files are hosted it shuts the server down.
+    let cancel = CancellationToken::new();
+    let interrupted = tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            tokio::signal::ctrl_c().await.expect("ctrl-c handler");
+            cancel.cancel();
+        }
+    });
 
-    let shared_state = Arc::new(repo);
+    let followed = match &args.path {
+        Some(path) => add_path(&args, path, &mut repo, &cancel).await?,
+        None => Vec::new(),
+    };
+
+    if let Some(algorithm) = args.migrate_to {
+        let path = args
+            .signing_key
+            .as_ref()
+            .ok_or("--migrate-to requires --signing-key")?;
+        let pkcs8 = tokio::fs::read(path).await?;
+        let key = Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| e.to_string())?;
+
+        for alias in repo
+            .migrate(algorithm, &key)
+            .map_err(|e| format!("migration failed: {:?}", e))?
+        {
+            println!("{} -> {}\n{:#}", alias.old, alias.new, alias.new);
+        }
+    }
+
+    let shared_state = Arc::new(RwLock::new(repo));
+    if !followed.is_empty() {
+        tokio::spawn(follow(shared_state.clone(), followed, args.keep_versions));
+    }
 
     let app = Router::new()
         .route("/hashes", get(get_hashes))
         .route("/piece/:hashId/:pieceIdx", get(get_piece))
+        .route("/piece/:hashId/:pieceIdx/raw", get(get_raw_piece))
+        .route("/block/:hashId/:pieceIdx/:blockIdx", get(get_block))
+        .route("/range/:hashId/:offset/:len", get(get_range))
+        .route("/alias/:hashId", get(get_alias))
+        .route("/manifest/:hashId", get(get_manifest))
+        .route("/dir/:hashId/:pieceIdx/*path", get(get_directory_piece))
         .layer(Extension(shared_state));
 
     let addr = SocketAddr::from(([127, 0, 0, 1], args.port));
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            interrupted.await.ok();
+        })
         .await?;
 
     Ok(())
 }
 
+/// Hashes the file or the directory at `path` and adds it to the repo, returns the files to
+/// follow.
+async fn add_path(
+    args: &Args,
+    path: &str,
+    repo: &mut FileRepo,
+    cancel: &CancellationToken,
+) -> Result<Vec<(GrowingFile, tokio::fs::File, String)>, Box<dyn std::error::Error>> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if (args.encrypt.is_some() || args.erasure.is_some()) && metadata.is_dir() {
+        return Err("--encrypt and --erasure only support a single file".into());
+    }
+    if args.follow && (metadata.is_dir() || args.mmap || args.encrypt.is_some()) {
+        return Err(
+            "--follow only supports a single file that isn't memory mapped or encrypted".into(),
+        );
+    }
+    if args.erasure.is_some() && (args.cdc.is_some() || args.encrypt.is_some() || args.follow) {
+        return Err(
+            "--erasure needs fixed size chunks and can't be combined with --encrypt".into(),
+        );
+    }
+
+    if args.checkpoint && (metadata.is_dir() || args.mmap || args.encrypt.is_some() || args.follow)
+    {
+        return Err("--checkpoint only supports a single file that is read from disk".into());
+    }
+
+    let mut followed = Vec::new();
+    for &algorithm in &args.hashes {
+        if cancel.is_cancelled() {
+            return Err("hashing cancelled".into());
+        }
+
+        let chunk_size = match args.chunk_size {
+            Some(chunk_size) => chunk_size,
+            None if metadata.is_dir() => CHUNK_BYTES,
+            None => chunk_size_for(metadata.len()),
+        };
+
+        let builder = File::builder().hash(algorithm);
+        let builder = match args.cdc {
+            Some(params) => builder.content_defined(params),
+            None => builder.chunk_size(chunk_size),
+        };
+
+        if metadata.is_dir() {
+            let directory = Directory::open(path, builder)
+                .await
+                .map_err(|e| format!("failed to hash {}: {:?}", path, e))?;
+            let root = directory
+                .root()
+                .map_err(|e| format!("failed to hash {}: {:?}", path, e))?;
+            println!("{} {} (directory)\n{:#}", algorithm, root, root);
+            repo.add_directory(directory)
+                .map_err(|e| format!("failed to add {}: {:?}", path, e))?;
+            continue;
+        }
+
+        if let Some(mode) = args.encrypt {
+            let data = tokio::fs::read(path).await?;
+            let key = match mode {
+                KeyMode::Convergent => EncryptionKey::convergent(&data),
+                KeyMode::Secret => EncryptionKey::generate()
+                    .map_err(|e| format!("failed to generate a key: {:?}", e))?,
+            };
+            let (file, capability) = builder
+                .build_encrypted(&data, key)
+                .map_err(|e| format!("failed to encrypt {}: {:?}", path, e))?;
+            println!(
+                "{} {} (encrypted)\n{}",
+                algorithm,
+                capability.root,
+                serde_json::to_string(&capability)?
+            );
+            repo.add(file)
+                .map_err(|e| format!("failed to add {}: {:?}", path, e))?;
+            continue;
+        }
+
+        if args.follow {
+            let mut source = tokio::fs::File::open(path).await?;
+            let mut growing = builder
+                .growing()
+                .map_err(|e| format!("failed to follow {}: {:?}", path, e))?;
+            growing
+                .read_from(&mut source)
+                .await
+                .map_err(|e| format!("failed to read {}: {:?}", path, e))?;
+            let file = growing
+                .snapshot()
+                .map_err(|e| format!("failed to hash {}: {:?}", path, e))?;
+            let root = file
+                .get_root()
+                .map_err(|e| format!("failed to hash {}: {:?}", path, e))?;
+            println!(
+                "{} {} ({} bytes)\n{:#}",
+                algorithm,
+                root,
+                file.byte_len(),
+                root
+            );
+            repo.add(file)
+                .map_err(|e| format!("failed to add {}: {:?}", path, e))?;
+            followed.push((growing, source, root.to_hex()));
+            continue;
+        }
+
+        let mut reported = 0;
+        let progress = |p: &IngestProgress| {
+            if args.progress && print_progress(algorithm, p, reported) {
+                reported = p.bytes_read;
+            }
+        };
+        let file = if args.mmap {
+            builder.mmap(path)
+        } else if args.checkpoint {
+            let sidecar = format!("{}.{}.checkpoint", path, algorithm);
+            open_checkpointed(builder, path, &sidecar, cancel, progress).await
+        } else {
+            builder.open_with_progress(path, cancel, progress).await
+        };
+        let file = match file {
+            Err(FileError::Cancelled) => return Err("hashing cancelled".into()),
+            file => file.map_err(|e| format!("failed to hash {}: {:?}", path, e))?,
+        };
+        let root = file
+            .get_root()
+            .map_err(|e| format!("failed to hash {}: {:?}", path, e))?;
+        println!("{} {}\n{:#}", algorithm, root, root);
+
+        if let Some(params) = args.erasure {
+            let (coded, info) = params
+                .encode(&file)
+                .map_err(|e| format!("failed to erasure code {}: {:?}", path, e))?;
+            println!(
+                "{} {} (erasure coded)\n{}",
+                algorithm,
+                info.coded_root,
+                serde_json::to_string(&info)?
+            );
+            repo.add(coded)
+                .map_err(|e| format!("failed to add {}: {:?}", path, e))?;
+        }
+        repo.add(file)
+            .map_err(|e| format!("failed to add {}: {:?}", path, e))?;
+    }
+
+    Ok(followed)
+}
+
+/// Prints a line for the progress of hashing if the phase changed or enough bytes were hashed
+/// since the last line at `reported` bytes, returns whether it printed.
+/// Hashes a file for `--checkpoint`, resuming from its sidecar if there is one. A sidecar of a
+/// file that changed since it was written, or that was written with other hashing options, is
+/// removed and the file is hashed from the start.
+async fn open_checkpointed(
+    builder: FileBuilder,
+    path: &str,
+    sidecar: &str,
+    cancel: &CancellationToken,
+    mut progress: impl FnMut(&IngestProgress),
+) -> Result<File, FileError> {
+    if std::path::Path::new(sidecar).exists() {
+        let resumed = builder
+            .resume_with_progress(path, sidecar, CHECKPOINT_BYTES, cancel, &mut progress)
+            .await;
+        match resumed {
+            Err(FileError::Changed | FileError::ChunkSize) => {
+                eprintln!("Discarding the stale checkpoint {}", sidecar);
+                std::fs::remove_file(sidecar).map_err(|_| FileError::File)?;
+            }
+            file => return file,
+        }
+    }
+    builder
+        .open_checkpointed_with_progress(path, sidecar, CHECKPOINT_BYTES, cancel, progress)
+        .await
+}
+
+fn print_progress(algorithm: HashAlgorithm, progress: &IngestProgress, reported: u64) -> bool {
+    let mib = |bytes: u64| bytes >> 20;
+    match progress.phase {
+        IngestPhase::Reading if progress.bytes_read - reported < PROGRESS_BYTES => false,
+        IngestPhase::Reading => {
+            let total = progress.total_bytes.unwrap_or_default();
+            eprintln!(
+                "{}: hashed {} of {} MiB ({} chunks)",
+                algorithm,
+                mib(progress.bytes_read),
+                mib(total),
+                progress.chunks_hashed
+            );
+            true
+        }
+        IngestPhase::BuildingTree => {
+            eprintln!("{}: building the merkle tree", algorithm);
+            true
+        }
+        IngestPhase::Done => false,
+    }
+}
+
+/// Polls the followed files and adds every snapshot that grew as the next version. Previous
+/// snapshots are still served, only the oldest ones past `keep_versions` are removed.
+async fn follow(
+    repo: Arc<RwLock<FileRepo>>,
+    mut followed: Vec<(GrowingFile, tokio::fs::File, String)>,
+    keep_versions: Option<usize>,
+) {
+    let mut previous = vec![VecDeque::new(); followed.len()];
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        for ((growing, source, hash), previous) in followed.iter_mut().zip(&mut previous) {
+            match growing.read_from(source).await {
+                Ok(0) => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("Failed to read the followed file: {:?}", e);
+                    continue;
+                }
+            }
+
+            let file = match growing.snapshot() {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Failed to snapshot the followed file: {:?}", e);
+                    continue;
+                }
+            };
+            let len = file.byte_len();
+            let mut repo = repo.write().expect("repo lock");
+            let version = match repo.add_version(hash, file) {
+                Ok(version) => version,
+                Err(e) => {
+                    eprintln!("Failed to add a version of the followed file: {:?}", e);
+                    continue;
+                }
+            };
+            println!(
+                "{} ({} bytes, version {})",
+                version.root, len, version.version
+            );
+
+            previous.push_back(std::mem::replace(hash, version.root.to_hex()));
+            while previous.len() > keep_versions.unwrap_or(usize::MAX) {
+                if let Some(old) = previous.pop_front() {
+                    if let Err(e) = repo.remove(&old) {
+                        eprintln!("Failed to remove the snapshot {}: {:?}", old, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
 async fn get_hashes(
-    Extension(repo): Extension<Arc<FileRepo>>,
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
 ) -> Result<Json<Vec<FileDescription>>, ApiError> {
-    let res = repo.get_available();
+    let repo = repo.read().expect("repo lock");
+    let res = repo.get_available()?;
     Ok(Json(res))
 }
 
+/// Query of the piece endpoints, `?encoding=deflate` asks for a compressed piece. The piece is
+/// sent uncompressed if it doesn't get smaller, its `encoding` tells which one was used.
+#[derive(Deserialize)]
+struct PieceQuery {
+    #[serde(default)]
+    encoding: Compression,
+}
+
 async fn get_piece(
-    Extension(repo): Extension<Arc<FileRepo>>,
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
     Path((hash, piece)): Path<(String, usize)>,
+    Query(query): Query<PieceQuery>,
 ) -> Result<Json<Piece>, ApiError> {
-    let res = repo.get_piece(hash, piece)?;
+    let repo = repo.read().expect("repo lock");
+    let res = repo
+        .get_piece(repo_key(&hash), piece)?
+        .compress(query.encoding);
     Ok(Json(res))
 }
 
+/// Responds with the bytes of a piece as the body, the proof hashes as a comma separated
+/// `x-pmtorrent-proof` header and the encoding of the body as `x-pmtorrent-encoding`. The chunk
+/// data of an uncompressed piece is passed to the response without copying.
+async fn get_raw_piece(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path((hash, piece)): Path<(String, usize)>,
+    Query(query): Query<PieceQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let res = repo
+        .get_piece(repo_key(&hash), piece)?
+        .compress(query.encoding);
+    let proof: Vec<String> = res.proof.iter().map(|h| h.to_hex()).collect();
+    let headers = [
+        (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+        (
+            HeaderName::from_static("x-pmtorrent-proof"),
+            proof.join(","),
+        ),
+        (
+            HeaderName::from_static("x-pmtorrent-encoding"),
+            res.encoding.name().to_string(),
+        ),
+    ];
+
+    Ok((headers, res.content.data))
+}
+
+async fn get_block(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path((hash, piece, block)): Path<(String, usize, usize)>,
+) -> Result<Json<BlockPiece>, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let res = repo.get_block(&repo_key(&hash), piece, block)?;
+    Ok(Json(res))
+}
+
+async fn get_range(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path((hash, offset, len)): Path<(String, u64, usize)>,
+) -> Result<Json<RangePiece>, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let res = repo.read_at(&repo_key(&hash), offset, len)?;
+    Ok(Json(res))
+}
+
+async fn get_alias(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path(hash): Path<String>,
+) -> Result<Json<RootAlias>, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let res = repo
+        .get_alias(&repo_key(&hash))
+        .ok_or(RepoError::DoesntExist)?;
+    Ok(Json(res.clone()))
+}
+
+async fn get_manifest(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path(hash): Path<String>,
+) -> Result<Json<Manifest>, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let res = repo
+        .get_manifest(&repo_key(&hash))
+        .ok_or(RepoError::DoesntExist)?;
+    Ok(Json(res.clone()))
+}
+
+async fn get_directory_piece(
+    Extension(repo): Extension<Arc<RwLock<FileRepo>>>,
+    Path((hash, piece, path)): Path<(String, usize, String)>,
+    Query(query): Query<PieceQuery>,
+) -> Result<Json<DirectoryPiece>, ApiError> {
+    let repo = repo.read().expect("repo lock");
+    let mut res =
+        repo.get_directory_piece(&repo_key(&hash), path.trim_start_matches('/'), piece)?;
+    res.piece = res.piece.compress(query.encoding);
+    Ok(Json(res))
+}
+
+/// Repo key of a root in a URL, roots are accepted in hex or base32.
+fn repo_key(hash: &str) -> String {
+    hash.parse::<AnyHash>()
+        .map(|h| h.to_hex())
+        .unwrap_or_else(|_| hash.to_string())
+}
+
 enum ApiError {
     Repo(RepoError),
 }
@@ -78,6 +608,9 @@ impl IntoResponse for ApiError {
                 "Requested data does not exist",
             )
                 .into_response(),
+            ApiError::Repo(RepoError::File(FileError::Changed)) => {
+                (StatusCode::CONFLICT, "Requested file was changed on disk").into_response()
+            }
             ApiError::Repo(_) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong").into_response()
             }
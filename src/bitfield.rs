@@ -0,0 +1,302 @@
+use std::ops::Range;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// A compact set of piece indices, e.g. the pieces a peer has or needs.
+///
+/// Bits are stored most significant bit first, so the first byte holds pieces 0 to 7 with piece
+/// 0 in its highest bit (the same layout as a BitTorrent `bitfield` message). The unused bits of
+/// the last byte are always zero.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::Bitfield;
+///
+/// let mut have = Bitfield::new(10);
+/// have.set(0);
+/// have.set(3);
+/// have.set(4);
+///
+/// let want = Bitfield::full(10).difference(&have);
+/// assert_eq!(want.count_ones(), 7);
+/// assert_eq!(have.missing_ranges().collect::<Vec<_>>(), vec![1..3, 5..10]);
+/// assert_eq!(have.to_bytes(), vec![0b1001_1000, 0]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bitfield {
+    bits: Vec<u8>,
+    len: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BitfieldError {
+    /// The number of bytes doesn't match the number of bits or an unused bit is set.
+    Length,
+
+    /// The provided text is not valid base64.
+    Encoding,
+}
+
+impl Bitfield {
+    /// Creates a bitfield of `len` bits with none of them set.
+    pub fn new(len: usize) -> Self {
+        Self {
+            bits: vec![0; len.div_ceil(8)],
+            len,
+        }
+    }
+
+    /// Creates a bitfield of `len` bits with all of them set.
+    pub fn full(len: usize) -> Self {
+        let mut bitfield = Self {
+            bits: vec![0xff; len.div_ceil(8)],
+            len,
+        };
+        bitfield.clear_unused();
+        bitfield
+    }
+
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Result<Self, BitfieldError> {
+        let bitfield = Self {
+            bits: bytes.to_vec(),
+            len,
+        };
+
+        let mut expected = bitfield.clone();
+        expected.clear_unused();
+        if bytes.len() != len.div_ceil(8) || expected != bitfield {
+            return Err(BitfieldError::Length);
+        }
+        Ok(bitfield)
+    }
+
+    pub fn from_base64(s: &str, len: usize) -> Result<Self, BitfieldError> {
+        let bytes = base64::decode(s).map_err(|_| BitfieldError::Encoding)?;
+        Self::from_bytes(&bytes, len)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits.clone()
+    }
+
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.bits)
+    }
+
+    /// Number of bits in the bitfield.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sets the bit at idx, does nothing if the idx is out of bounds.
+    pub fn set(&mut self, idx: usize) {
+        if idx < self.len {
+            self.bits[idx / 8] |= 0x80 >> (idx % 8);
+        }
+    }
+
+    /// Clears the bit at idx, does nothing if the idx is out of bounds.
+    pub fn unset(&mut self, idx: usize) {
+        if idx < self.len {
+            self.bits[idx / 8] &= !(0x80 >> (idx % 8));
+        }
+    }
+
+    /// Returns true if the bit at idx is set, out of bounds bits are never set.
+    pub fn test(&self, idx: usize) -> bool {
+        idx < self.len && self.bits[idx / 8] & (0x80 >> (idx % 8)) != 0
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    pub fn count_zeros(&self) -> usize {
+        self.len - self.count_ones()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.count_ones() == self.len
+    }
+
+    /// Indices of the set bits in ascending order.
+    pub fn iter_ones(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&i| self.test(i))
+    }
+
+    /// Indices of the unset bits in ascending order.
+    pub fn iter_zeros(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&i| !self.test(i))
+    }
+
+    /// Ranges of consecutive unset bits, e.g. ranges of pieces that still have to be fetched.
+    pub fn missing_ranges(&self) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut idx = 0;
+        std::iter::from_fn(move || {
+            while idx < self.len && self.test(idx) {
+                idx += 1;
+            }
+            if idx == self.len {
+                return None;
+            }
+
+            let start = idx;
+            while idx < self.len && !self.test(idx) {
+                idx += 1;
+            }
+            Some(start..idx)
+        })
+    }
+
+    /// Bits that are set in both bitfields, the result has the length of the shorter one.
+    pub fn intersection(&self, other: &Bitfield) -> Bitfield {
+        self.combine(other, |a, b| a & b)
+    }
+
+    /// Bits that are set in `self`, but not in `other`, the result has the length of `self`.
+    pub fn difference(&self, other: &Bitfield) -> Bitfield {
+        let mut bitfield = Self {
+            bits: self
+                .bits
+                .iter()
+                .enumerate()
+                .map(|(i, a)| a & !other.bits.get(i).copied().unwrap_or(0))
+                .collect(),
+            len: self.len,
+        };
+        bitfield.clear_unused();
+        bitfield
+    }
+
+    fn combine(&self, other: &Bitfield, f: impl Fn(u8, u8) -> u8) -> Bitfield {
+        let len = self.len.min(other.len);
+        let mut bitfield = Self {
+            bits: self
+                .bits
+                .iter()
+                .zip(other.bits.iter())
+                .take(len.div_ceil(8))
+                .map(|(a, b)| f(*a, *b))
+                .collect(),
+            len,
+        };
+        bitfield.clear_unused();
+        bitfield
+    }
+
+    fn clear_unused(&mut self) {
+        if !self.len.is_multiple_of(8) {
+            if let Some(last) = self.bits.last_mut() {
+                *last &= 0xff << (8 - self.len % 8);
+            }
+        }
+    }
+}
+
+/// A bitfield is serialized as its length together with base64 encoded bytes.
+#[derive(Serialize, Deserialize)]
+struct SerializedBitfield {
+    len: usize,
+    bits: String,
+}
+
+impl Serialize for Bitfield {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        SerializedBitfield {
+            len: self.len,
+            bits: self.to_base64(),
+        }
+        .serialize(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bitfield {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = SerializedBitfield::deserialize(d)?;
+        Bitfield::from_base64(&s.bits, s.len)
+            .map_err(|e| serde::de::Error::custom(format!("invalid bitfield: {:?}", e)))
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_set_and_test() {
+        use super::*;
+
+        let mut bitfield = Bitfield::new(12);
+        assert_eq!(bitfield.count_ones(), 0);
+        assert_eq!(bitfield.to_bytes().len(), 2);
+
+        bitfield.set(0);
+        bitfield.set(11);
+        bitfield.set(12); // out of bounds, ignored.
+        assert!(bitfield.test(0) && bitfield.test(11));
+        assert!(!bitfield.test(1) && !bitfield.test(12));
+        assert_eq!(bitfield.to_bytes(), vec![0x80, 0x10]);
+        assert_eq!(bitfield.iter_ones().collect::<Vec<usize>>(), vec![0, 11]);
+        assert_eq!(bitfield.count_zeros(), 10);
+
+        bitfield.unset(0);
+        assert!(!bitfield.test(0));
+
+        let full = Bitfield::full(12);
+        assert!(full.is_full());
+        assert_eq!(full.to_bytes(), vec![0xff, 0xf0]);
+        assert_eq!(full.missing_ranges().count(), 0);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        use super::*;
+
+        let a = Bitfield::from_bytes(&[0b1100_1100, 0b1000_0000], 9).unwrap();
+        let b = Bitfield::from_bytes(&[0b1010_1010, 0b1000_0000], 9).unwrap();
+
+        assert_eq!(
+            a.intersection(&b).to_bytes(),
+            vec![0b1000_1000, 0b1000_0000]
+        );
+        assert_eq!(a.difference(&b).to_bytes(), vec![0b0100_0100, 0]);
+        assert_eq!(
+            a.missing_ranges().collect::<Vec<Range<usize>>>(),
+            vec![2..4, 6..8]
+        );
+
+        let short = Bitfield::full(4);
+        assert_eq!(a.intersection(&short).to_bytes(), vec![0b1100_0000]);
+        assert_eq!(a.difference(&short).len(), 9);
+    }
+
+    #[test]
+    fn test_encoding() {
+        use super::*;
+
+        let mut bitfield = Bitfield::new(20);
+        bitfield.set(3);
+        bitfield.set(19);
+
+        let decoded = Bitfield::from_base64(&bitfield.to_base64(), 20).unwrap();
+        assert_eq!(decoded, bitfield);
+
+        let json = serde_json::to_string(&bitfield).unwrap();
+        assert_eq!(serde_json::from_str::<Bitfield>(&json).unwrap(), bitfield);
+
+        assert_eq!(Bitfield::from_bytes(&[0xff], 4), Err(BitfieldError::Length));
+        assert_eq!(
+            Bitfield::from_bytes(&[0xff, 0], 8),
+            Err(BitfieldError::Length)
+        );
+        assert_eq!(Bitfield::from_base64("!", 8), Err(BitfieldError::Encoding));
+    }
+}
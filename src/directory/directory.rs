@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    AnyHash, Compression, DirectoryPiece, File, FileBuilder, FileError, HashAlgorithm, Manifest,
+    ManifestEntry, ManifestTree, Piece,
+};
+
+/// A directory of files that is identified by a single root.
+///
+/// Every file is hashed into its own merkle tree and the [`Manifest`] of relative paths, sizes
+/// and file roots is committed under the directory root. A [`DirectoryPiece`] proves both that a
+/// chunk belongs to a file root and that the file root belongs to a path of the directory.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{Directory, File, HashAlgorithm};
+///
+/// let files = vec![
+///     ("docs/readme.txt".to_string(), File::new(b"hello").unwrap()),
+///     ("data.bin".to_string(), File::new(&[7u8; 5000]).unwrap()),
+/// ];
+/// let directory = Directory::from_files(HashAlgorithm::Sha256, files).unwrap();
+/// let root = directory.root().unwrap();
+///
+/// let piece = directory.get_piece("data.bin", 3).unwrap();
+/// assert!(piece.verify(&root, "data.bin", 3).is_ok());
+/// assert!(piece.verify(&root, "docs/readme.txt", 3).is_err());
+/// ```
+pub struct Directory {
+    manifest: Manifest,
+    tree: ManifestTree,
+    /// Files in the order of the manifest entries.
+    files: Vec<File>,
+}
+
+impl Directory {
+    /// Creates a directory from files and their relative paths, the directory root is hashed
+    /// with the provided algorithm.
+    pub fn from_files(
+        algorithm: HashAlgorithm,
+        files: Vec<(String, File)>,
+    ) -> Result<Self, FileError> {
+        let mut files = files;
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let entries = files
+            .iter()
+            .map(|(path, file)| {
+                Ok(ManifestEntry {
+                    path: path.clone(),
+                    size: file.byte_len(),
+                    pieces: file.get_size(),
+                    chunking: file.chunking(),
+                    root: file.get_root()?,
+                })
+            })
+            .collect::<Result<Vec<ManifestEntry>, FileError>>()?;
+
+        let manifest = Manifest::new(algorithm, entries)?;
+        let tree = ManifestTree::new(&manifest)?;
+        let files = files.into_iter().map(|(_, file)| file).collect();
+
+        Ok(Self {
+            manifest,
+            tree,
+            files,
+        })
+    }
+
+    /// Walks the directory at `path` recursively and opens every regular file with the builder,
+    /// see [`FileBuilder::open`]. Symlinks that point outside of `path` and dangling ones are
+    /// skipped, an empty directory has a manifest without entries. The directory root is hashed
+    /// with the algorithm of the builder.
+    pub async fn open<P: AsRef<Path>>(path: P, builder: FileBuilder) -> Result<Self, FileError> {
+        let base = path.as_ref();
+        let mut files = vec![];
+
+        for file_path in walk(base).await? {
+            let relative = file_path
+                .strip_prefix(base)
+                .map_err(|_| FileError::File)?
+                .components()
+                .map(|c| c.as_os_str().to_str().ok_or(FileError::File))
+                .collect::<Result<Vec<&str>, FileError>>()?
+                .join("/");
+
+            files.push((relative, builder.open(&file_path).await?));
+        }
+
+        Self::from_files(builder.hasher().algorithm, files)
+    }
+
+    pub fn root(&self) -> Result<AnyHash, FileError> {
+        self.tree.root()
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn file(&self, path: &str) -> Option<&File> {
+        self.manifest.find(path).map(|idx| &self.files[idx])
+    }
+
+    /// Returns the piece at `idx` of the file at `path` with the proof of its manifest entry.
+    pub fn get_piece(&self, path: &str, idx: usize) -> Result<DirectoryPiece, FileError> {
+        let entry_idx = self.manifest.find(path).ok_or(FileError::File)?;
+        let (content, proof) = self.files[entry_idx].get_chunk(idx)?;
+
+        Ok(DirectoryPiece {
+            entry: self.manifest.entries[entry_idx].clone(),
+            entry_idx,
+            entry_proof: self.tree.entry_proof(entry_idx)?,
+            piece: Piece {
+                content,
+                proof,
+                encoding: Compression::None,
+            },
+        })
+    }
+
+    /// Splits the directory in its manifest, manifest tree and files, e.g. to add the files to a
+    /// [`crate::FileRepo`].
+    pub fn into_parts(self) -> (Manifest, ManifestTree, Vec<File>) {
+        (self.manifest, self.tree, self.files)
+    }
+}
+
+/// Paths of all regular files under `base`.
+///
+/// Symlinks are followed as long as their targets stay under `base`, links that point outside
+/// of it or whose target doesn't exist are skipped. Every directory is walked once, so a link to one of its parents doesn't
+/// loop forever.
+async fn walk(base: &Path) -> Result<Vec<PathBuf>, FileError> {
+    let real_base = canonicalize(base).await?;
+    let mut visited = HashSet::from([real_base.clone()]);
+    let mut files = vec![];
+    let mut dirs = vec![(base.to_path_buf(), real_base.clone())];
+
+    while let Some((dir, real_dir)) = dirs.pop() {
+        let mut read_dir = tokio::fs::read_dir(&dir)
+            .await
+            .map_err(|_| FileError::File)?;
+
+        while let Some(entry) = read_dir.next_entry().await.map_err(|_| FileError::File)? {
+            let file_type = entry.file_type().await.map_err(|_| FileError::File)?;
+            let real = match file_type.is_symlink() {
+                true => match tokio::fs::canonicalize(entry.path()).await {
+                    Ok(real) => real,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(_) => return Err(FileError::File),
+                },
+                false => real_dir.join(entry.file_name()),
+            };
+            if !real.starts_with(&real_base) {
+                continue;
+            }
+
+            let metadata = tokio::fs::metadata(&real)
+                .await
+                .map_err(|_| FileError::File)?;
+
+            if metadata.is_dir() {
+                if visited.insert(real.clone()) {
+                    dirs.push((entry.path(), real));
+                }
+            } else if metadata.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+async fn canonicalize(path: &Path) -> Result<PathBuf, FileError> {
+    tokio::fs::canonicalize(path)
+        .await
+        .map_err(|_| FileError::File)
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_open_directory() {
+        use super::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        std::fs::write(dir.path().join("top.txt"), b"top").unwrap();
+        std::fs::write(dir.path().join("a/b/deep.bin"), vec![1u8; 3000]).unwrap();
+        std::fs::write(dir.path().join("a/empty"), b"").unwrap();
+
+        let builder = File::builder().hash(HashAlgorithm::Sha384);
+        let directory = Directory::open(dir.path(), builder).await.unwrap();
+        let root = directory.root().unwrap();
+        assert_eq!(root.algorithm(), HashAlgorithm::Sha384);
+        assert_eq!(directory.manifest().root().unwrap(), root);
+
+        let paths: Vec<&str> = directory
+            .manifest()
+            .entries
+            .iter()
+            .map(|e| e.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["a/b/deep.bin", "a/empty", "top.txt"]);
+
+        let entry = &directory.manifest().entries[0];
+        assert_eq!((entry.size, entry.pieces), (3000, 3));
+        assert_eq!(directory.manifest().entries[1].size, 0);
+
+        for idx in 0..3 {
+            let piece = directory.get_piece("a/b/deep.bin", idx).unwrap();
+            assert!(piece.verify(&root, "a/b/deep.bin", idx).is_ok());
+        }
+
+        // A piece of another file can't be passed off as a piece of this path.
+        let mut piece = directory.get_piece("top.txt", 0).unwrap();
+        assert!(piece.verify(&root, "a/b/deep.bin", 0).is_err());
+        piece.entry.path = "a/b/deep.bin".to_string();
+        assert!(piece.verify(&root, "a/b/deep.bin", 0).is_err());
+
+        // The same directory with another file content has another root.
+        std::fs::write(dir.path().join("top.txt"), b"TOP").unwrap();
+        let changed = Directory::open(dir.path(), builder).await.unwrap();
+        assert_ne!(changed.root().unwrap(), root);
+
+        let empty = tempfile::tempdir().unwrap();
+        let directory = Directory::open(empty.path(), builder).await.unwrap();
+        assert!(directory.manifest().entries.is_empty());
+        assert!(directory.get_piece("top.txt", 0).is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_open_directory_symlinks() {
+        use super::*;
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret"), b"secret").unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::write(dir.path().join("a/file"), b"file").unwrap();
+
+        // A link to a file in the directory, a loop back to the directory, two links that escape
+        // it and a dangling one.
+        symlink(dir.path().join("a/file"), dir.path().join("link")).unwrap();
+        symlink("..", dir.path().join("a/loop")).unwrap();
+        symlink(outside.path(), dir.path().join("outside")).unwrap();
+        symlink(outside.path().join("secret"), dir.path().join("a/secret")).unwrap();
+        symlink(dir.path().join("missing"), dir.path().join("a/dangling")).unwrap();
+
+        let directory = Directory::open(dir.path(), File::builder()).await.unwrap();
+        let paths: Vec<&str> = directory
+            .manifest()
+            .entries
+            .iter()
+            .map(|e| e.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["a/file", "link"]);
+    }
+}
@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+
+use crate::merkle::{self, MerkleError, MerkleTree};
+use crate::{
+    root_from_partial, AnyHash, AnyHasher, AsBytes, ChunkHasher, Chunking, FileError, FileMeta,
+    HashAlgorithm, Hasher, Piece,
+};
+
+const ENTRY_DOMAIN: &[u8] = b"pmtorrent-manifest-entry";
+
+/// A file of a [`crate::Directory`]: its relative path, size and the root of its own merkle tree.
+///
+/// The number of pieces and the chunking are committed as well, so a piece of the file can be
+/// verified against `root` with nothing but the entry.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the directory with `/` as the separator.
+    pub path: String,
+    pub size: u64,
+    pub pieces: usize,
+    pub chunking: Chunking,
+    pub root: AnyHash,
+}
+
+impl ManifestEntry {
+    /// The leaf hasher of the merkle tree of the file.
+    pub fn hasher(&self) -> ChunkHasher {
+        ChunkHasher {
+            algorithm: self.root.algorithm(),
+            chunking: self.chunking,
+        }
+    }
+
+    /// The length and the number of chunks that are committed into the file root.
+    pub fn meta(&self) -> FileMeta {
+        FileMeta {
+            len: self.size,
+            chunk_count: self.pieces,
+        }
+    }
+
+    /// The bytes that are hashed as a leaf of the manifest tree.
+    fn encode(&self) -> EntryLeaf {
+        let mut bytes = [ENTRY_DOMAIN, self.path.as_bytes(), &[0]].concat();
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&(self.pieces as u64).to_be_bytes());
+        bytes.extend_from_slice(&self.chunking.encode());
+        bytes.extend_from_slice(self.root.algorithm().name().as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(self.root.as_bytes());
+
+        EntryLeaf(bytes)
+    }
+}
+
+/// The list of files of a [`crate::Directory`] sorted by path.
+///
+/// The entries are the leaves of a merkle tree and its root is the root of the directory, so the
+/// manifest can be checked against a trusted directory root with [`Manifest::root`] and a single
+/// entry with [`verify_entry`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub algorithm: HashAlgorithm,
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    /// Creates a manifest from entries in any order, e.g. none for an empty directory. Returns
+    /// `FileError::File` if a path is not a valid relative path, e.g. it's empty, absolute,
+    /// contains `..`, a NUL byte or a duplicate.
+    pub fn new(
+        algorithm: HashAlgorithm,
+        mut entries: Vec<ManifestEntry>,
+    ) -> Result<Self, FileError> {
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let duplicates = entries.windows(2).any(|w| w[0].path == w[1].path);
+        if duplicates || !entries.iter().all(|e| is_relative(&e.path)) {
+            return Err(FileError::File);
+        }
+
+        Ok(Self { algorithm, entries })
+    }
+
+    /// Index of the entry with the provided path.
+    pub fn find(&self, path: &str) -> Option<usize> {
+        self.entries
+            .binary_search_by(|e| e.path.as_str().cmp(path))
+            .ok()
+    }
+
+    /// Calculates the directory root from the entries.
+    pub fn root(&self) -> Result<AnyHash, FileError> {
+        ManifestTree::new(self)?.root()
+    }
+}
+
+fn is_relative(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains('\0')
+        && path
+            .split('/')
+            .all(|c| !c.is_empty() && c != "." && c != "..")
+}
+
+/// A merkle tree over the entries of a [`Manifest`], padded with zero hashes like
+/// [`crate::ChunkMerkleTree`].
+pub struct ManifestTree {
+    tree: Vec<AnyHash>,
+}
+
+impl ManifestTree {
+    pub fn new(manifest: &Manifest) -> Result<Self, FileError> {
+        let leaves: Vec<EntryLeaf> = manifest.entries.iter().map(|e| e.encode()).collect();
+        let tree = Self::build_tree(&AnyHasher(manifest.algorithm), &leaves)?;
+
+        Ok(Self { tree })
+    }
+
+    pub fn root(&self) -> Result<AnyHash, FileError> {
+        Ok(self
+            .tree
+            .last()
+            .ok_or(FileError::Merkle(MerkleError::InvalidIdx))?
+            .to_owned())
+    }
+
+    /// Proof hashes for the entry at the provided index.
+    pub fn entry_proof(&self, idx: usize) -> Result<Vec<AnyHash>, FileError> {
+        Ok(self.get_proof_hashes(idx)?)
+    }
+}
+
+impl MerkleTree<EntryLeaf, AnyHasher> for ManifestTree {
+    fn get_tree(&self) -> &[AnyHash] {
+        &self.tree
+    }
+
+    fn build_first_level(
+        hasher: &AnyHasher,
+        leaves: &[EntryLeaf],
+    ) -> Result<Vec<AnyHash>, MerkleError> {
+        let mut hashes: Vec<AnyHash> = leaves.iter().map(|l| hasher.digest(l.as_bytes())).collect();
+        hashes.resize(
+            leaves.len().next_power_of_two(),
+            hasher.algorithm().zero_hash(),
+        );
+        Ok(hashes)
+    }
+}
+
+/// Encoded [`ManifestEntry`].
+struct EntryLeaf(Vec<u8>);
+
+impl AsBytes for EntryLeaf {
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Verifies that `entry` (i.e. its path, size and file root) is the entry at `entry_idx` of the
+/// manifest committed by the directory root. The height of the manifest tree is taken from the
+/// length of the proof.
+pub fn verify_entry(
+    directory_root: &AnyHash,
+    entry: &ManifestEntry,
+    entry_idx: usize,
+    proof: Vec<AnyHash>,
+) -> Result<(), FileError> {
+    if proof.len() >= usize::BITS as usize {
+        return Err(FileError::Proof);
+    }
+
+    let hasher = AnyHasher(directory_root.algorithm());
+    let leaf_count = 1 << proof.len();
+    let root = merkle::root_from_partial(&hasher, &entry.encode(), entry_idx, leaf_count, proof)
+        .map_err(|_| FileError::Proof)?;
+
+    if &root != directory_root {
+        return Err(FileError::Proof);
+    }
+    Ok(())
+}
+
+/// A piece of a file in a directory with both proofs: from the chunk to the file root and from
+/// the manifest entry (path to file root) to the directory root.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DirectoryPiece {
+    pub entry: ManifestEntry,
+    pub entry_idx: usize,
+    pub entry_proof: Vec<AnyHash>,
+    pub piece: Piece,
+}
+
+impl DirectoryPiece {
+    /// Verifies that the piece at `piece_idx` belongs to the file at `path` of the directory with
+    /// the provided root. A compressed piece is decompressed first.
+    pub fn verify(
+        &self,
+        directory_root: &AnyHash,
+        path: &str,
+        piece_idx: usize,
+    ) -> Result<(), FileError> {
+        if self.entry.path != path || piece_idx >= self.entry.pieces {
+            return Err(FileError::Proof);
+        }
+        verify_entry(
+            directory_root,
+            &self.entry,
+            self.entry_idx,
+            self.entry_proof.clone(),
+        )?;
+
+        let chunk = self
+            .piece
+            .decompress(self.entry.chunking.max_chunk_size())
+            .map_err(|_| FileError::Proof)?;
+        let root = root_from_partial(
+            &self.entry.hasher(),
+            &self.entry.meta(),
+            &chunk,
+            piece_idx,
+            self.piece.proof.clone(),
+        )
+        .map_err(|_| FileError::Proof)?;
+
+        if root != self.entry.root {
+            return Err(FileError::Proof);
+        }
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_manifest_paths() {
+        use super::*;
+
+        let entry = |path: &str| ManifestEntry {
+            path: path.to_string(),
+            size: 1,
+            pieces: 1,
+            chunking: Chunking::default(),
+            root: HashAlgorithm::Sha256.hasher().digest(path.as_bytes()),
+        };
+
+        let manifest = Manifest::new(
+            HashAlgorithm::Sha256,
+            vec![entry("b/c"), entry("a"), entry("b/a")],
+        )
+        .unwrap();
+        let paths: Vec<&str> = manifest.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec!["a", "b/a", "b/c"]);
+        assert_eq!(manifest.find("b/c"), Some(2));
+        assert_eq!(manifest.find("b"), None);
+
+        for bad in ["", "/a", "a//b", "a/../b", "./a", "a\0"] {
+            let res = Manifest::new(HashAlgorithm::Sha256, vec![entry(bad)]);
+            assert!(res.is_err(), "{:?}", bad);
+        }
+        assert!(Manifest::new(HashAlgorithm::Sha256, vec![entry("a"), entry("a")]).is_err());
+        let empty = Manifest::new(HashAlgorithm::Sha256, vec![]).unwrap();
+        assert!(empty.root().is_ok());
+
+        // Every entry is proven against the root, a modified entry is not.
+        let root = manifest.root().unwrap();
+        let tree = ManifestTree::new(&manifest).unwrap();
+        for (idx, e) in manifest.entries.iter().enumerate() {
+            assert!(verify_entry(&root, e, idx, tree.entry_proof(idx).unwrap()).is_ok());
+        }
+
+        let mut forged = manifest.entries[1].clone();
+        forged.root = manifest.entries[2].root.clone();
+        let res = verify_entry(&root, &forged, 1, tree.entry_proof(1).unwrap());
+        assert!(matches!(res, Err(FileError::Proof)));
+    }
+}
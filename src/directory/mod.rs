@@ -0,0 +1,6 @@
+#[allow(clippy::module_inception)]
+mod directory;
+mod manifest;
+
+pub use directory::*;
+pub use manifest::*;
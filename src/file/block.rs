@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+
+use crate::merkle::{self, MerkleError, MerkleTree};
+use crate::{AnyHash, AnyHasher, AsBytes, Chunk, ChunkHasher, FileError, FileMeta, Hasher};
+
+/// Size of a block inside a chunk in bytes.
+pub const BLOCK_BYTES: usize = 16 * 1024;
+
+/// A merkle tree over the blocks of a single chunk, similar to the piece layers of BEP 52.
+///
+/// The chunk (padded to the chunk size for fixed size chunking) is split in blocks of
+/// [`BLOCK_BYTES`], only the last block can be shorter, and the block hashes are padded with zero
+/// hashes to a power of two. The root of the tree is the leaf hash of the chunk, so a chunk that
+/// fits in a single block is hashed as a whole and the blocks of a larger chunk can be verified
+/// one by one, see [`BlockPiece`].
+pub struct BlockTree {
+    tree: Vec<AnyHash>,
+    block_count: usize,
+}
+
+impl BlockTree {
+    pub fn new(hasher: &ChunkHasher, chunk: &Chunk) -> Result<Self, FileError> {
+        let padded = hasher.padded(chunk);
+        let blocks: Vec<Chunk> = (0..hasher.block_count(chunk.len()))
+            .map(|idx| {
+                let start = idx * BLOCK_BYTES;
+                Chunk {
+                    data: padded.slice(start..(start + BLOCK_BYTES).min(padded.len())),
+                    leaf_idx: idx,
+                }
+            })
+            .collect();
+        let tree = Self::build_tree(&AnyHasher(hasher.algorithm), &blocks)?;
+
+        Ok(Self {
+            tree,
+            block_count: blocks.len(),
+        })
+    }
+
+    /// The leaf hash of the chunk.
+    pub fn root(&self) -> AnyHash {
+        self.tree.last().expect("a chunk has a block").to_owned()
+    }
+
+    /// Proof hashes for the block at the provided index, filler leaves are not blocks.
+    pub fn block_proof(&self, idx: usize) -> Result<Vec<AnyHash>, FileError> {
+        if idx >= self.block_count {
+            return Err(FileError::Merkle(MerkleError::InvalidIdx));
+        }
+        Ok(self.get_proof_hashes(idx)?)
+    }
+}
+
+impl MerkleTree<Chunk, AnyHasher> for BlockTree {
+    fn get_tree(&self) -> &[AnyHash] {
+        &self.tree
+    }
+
+    fn build_first_level(
+        hasher: &AnyHasher,
+        leaves: &[Chunk],
+    ) -> Result<Vec<AnyHash>, MerkleError> {
+        let mut hashes: Vec<AnyHash> = leaves.iter().map(|l| hasher.digest(l.as_bytes())).collect();
+        hashes.resize(
+            leaves.len().next_power_of_two(),
+            hasher.algorithm().zero_hash(),
+        );
+        Ok(hashes)
+    }
+}
+
+/// A block of a chunk with its proof to the leaf hash of the chunk and the proof of the leaf hash
+/// to the file root.
+///
+/// A client checks the leaf hash once and then every block as soon as it arrives, so a bad
+/// server is detected after a single block instead of a whole chunk.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{File, BLOCK_BYTES};
+///
+/// let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+/// let file = File::builder().chunk_size(4 * BLOCK_BYTES).build(&data).unwrap();
+/// let root = file.get_root().unwrap();
+///
+/// // The last chunk is 200_000 - 3 * 65536 = 3392 bytes, its other blocks are padding.
+/// let block = file.get_block(3, 0).unwrap();
+/// assert_eq!(block.content.len(), 3392);
+/// assert!(block.verify(&file.hasher(), &file.meta(), &root, 3, 0, 3392).is_ok());
+/// assert!(block.verify(&file.hasher(), &file.meta(), &root, 3, 1, 3392).is_err());
+/// assert_eq!(file.get_block(3, 1).unwrap().content.len(), 0);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlockPiece {
+    /// The data of the block without the padding of the chunk.
+    pub content: Chunk,
+    pub block_proof: Vec<AnyHash>,
+    pub leaf_hash: AnyHash,
+    pub leaf_proof: Vec<AnyHash>,
+}
+
+impl BlockPiece {
+    /// Verifies the leaf hash against the file root with [`verify_leaf_hash`] and the block
+    /// against the leaf hash with [`verify_block`].
+    pub fn verify(
+        &self,
+        hasher: &ChunkHasher,
+        meta: &FileMeta,
+        root: &AnyHash,
+        chunk_idx: usize,
+        block_idx: usize,
+        chunk_len: usize,
+    ) -> Result<(), FileError> {
+        verify_leaf_hash(
+            hasher,
+            meta,
+            root,
+            chunk_idx,
+            &self.leaf_hash,
+            self.leaf_proof.clone(),
+        )?;
+        verify_block(
+            hasher,
+            &self.leaf_hash,
+            chunk_len,
+            block_idx,
+            &self.content,
+            self.block_proof.clone(),
+        )
+    }
+}
+
+/// Verifies that `leaf_hash` is the hash of the chunk at `leaf_idx` of the file with the
+/// provided root.
+pub fn verify_leaf_hash(
+    hasher: &ChunkHasher,
+    meta: &FileMeta,
+    root: &AnyHash,
+    leaf_idx: usize,
+    leaf_hash: &AnyHash,
+    proof: Vec<AnyHash>,
+) -> Result<(), FileError> {
+    if leaf_idx >= meta.chunk_count {
+        return Err(FileError::Proof);
+    }
+
+    let leaf_count = meta.chunk_count.next_power_of_two();
+    let tree_root =
+        merkle::root_from_leaf_hash(hasher, leaf_hash.clone(), leaf_idx, leaf_count, proof)
+            .map_err(|_| FileError::Proof)?;
+
+    if &hasher.file_root(meta, &tree_root) != root {
+        return Err(FileError::Proof);
+    }
+    Ok(())
+}
+
+/// Verifies that `block` is the block at `block_idx` of a chunk with the provided leaf hash.
+///
+/// The shape of the block tree depends on the exact length of the chunk, it has to come from a
+/// trusted source and not from the server that sent the block: for fixed size chunking it's
+/// given by [`FileMeta::chunk_len`], for content-defined chunking by the list of chunk lengths.
+/// Returns `FileError::ChunkSize` if the block doesn't have its exact length.
+pub fn verify_block(
+    hasher: &ChunkHasher,
+    leaf_hash: &AnyHash,
+    chunk_len: usize,
+    block_idx: usize,
+    block: &Chunk,
+    proof: Vec<AnyHash>,
+) -> Result<(), FileError> {
+    let padded_len = hasher.padded_len(chunk_len);
+    let block_count = hasher.block_count(chunk_len);
+    if block_idx >= block_count {
+        return Err(FileError::Merkle(MerkleError::InvalidIdx));
+    }
+
+    let start = block_idx * BLOCK_BYTES;
+    if block.len() != chunk_len.saturating_sub(start).min(BLOCK_BYTES) {
+        return Err(FileError::ChunkSize);
+    }
+
+    let mut padded = block.data.to_vec();
+    padded.resize((padded_len - start).min(BLOCK_BYTES), 0);
+    let padded = Chunk {
+        data: padded.into(),
+        leaf_idx: block_idx,
+    };
+
+    let hasher = AnyHasher(hasher.algorithm);
+    let leaf_count = block_count.next_power_of_two();
+    let root = merkle::root_from_partial(&hasher, &padded, block_idx, leaf_count, proof)
+        .map_err(|_| FileError::Proof)?;
+
+    if &root != leaf_hash {
+        return Err(FileError::Proof);
+    }
+    Ok(())
+}
+
+mod tests {
+    #[test]
+    fn test_block_tree() {
+        use super::*;
+        use crate::{Chunking, HashAlgorithm};
+
+        let hasher = ChunkHasher::new(HashAlgorithm::Sha256, 3 * BLOCK_BYTES);
+        let chunk = Chunk {
+            data: vec![7u8; 2 * BLOCK_BYTES + 100].into(),
+            leaf_idx: 0,
+        };
+        assert_eq!(hasher.block_count(chunk.len()), 3);
+
+        let tree = BlockTree::new(&hasher, &chunk).unwrap();
+        assert_eq!(tree.root(), hasher.leaf_hash(&chunk));
+        assert_eq!(tree.get_leaf_count(), 4);
+        assert!(tree.block_proof(3).is_err());
+
+        // The last block is padded to the chunk size.
+        let last = Chunk {
+            data: chunk.data.slice(2 * BLOCK_BYTES..),
+            leaf_idx: 2,
+        };
+        let proof = tree.block_proof(2).unwrap();
+        let res = verify_block(&hasher, &tree.root(), chunk.len(), 2, &last, proof.clone());
+        assert!(res.is_ok());
+
+        // Explicit zero bytes instead of the padding are rejected.
+        let mut zeros = last.data.to_vec();
+        zeros.push(0);
+        let zeros = Chunk {
+            data: zeros.into(),
+            leaf_idx: 2,
+        };
+        let res = verify_block(&hasher, &tree.root(), chunk.len(), 2, &zeros, proof.clone());
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+
+        let res = verify_block(&hasher, &tree.root(), chunk.len(), 1, &last, proof);
+        assert!(res.is_err());
+
+        // A chunk that fits in a block is hashed as a whole.
+        let small = ChunkHasher {
+            chunking: Chunking::Fixed(1024),
+            ..hasher
+        };
+        let chunk = Chunk {
+            data: vec![1u8; 1000].into(),
+            leaf_idx: 0,
+        };
+        let mut padded = vec![1u8; 1000];
+        padded.resize(1024, 0);
+        assert_eq!(small.leaf_hash(&chunk), small.digest(&padded));
+    }
+}
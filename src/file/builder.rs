@@ -0,0 +1,477 @@
+use std::path::Path;
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    encrypted_chunking, CancellationToken, CdcParams, Chunk, ChunkHasher, ChunkMerkleTree,
+    ChunkStorage, Chunking, DiskFile, EncryptionKey, File, FileError, GrowingFile, HashAlgorithm,
+    IngestPhase, IngestProgress, MmapFile, ReadCapability, CHUNK_BYTES,
+};
+
+/// The smallest chunk size picked by [`chunk_size_for`].
+pub const MIN_AUTO_CHUNK_BYTES: usize = CHUNK_BYTES;
+
+/// The largest chunk size picked by [`chunk_size_for`].
+pub const MAX_AUTO_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+
+/// The number of chunks [`chunk_size_for`] aims for.
+const TARGET_CHUNK_COUNT: u64 = 2048;
+
+/// Picks a chunk size for a file of the provided length, similar to torrent piece sizing.
+///
+/// The chunk size is the smallest power of two that splits the file in at most 2048 chunks,
+/// clamped between [`MIN_AUTO_CHUNK_BYTES`] and [`MAX_AUTO_CHUNK_BYTES`].
+pub fn chunk_size_for(file_len: u64) -> usize {
+    let chunk_size = file_len.div_ceil(TARGET_CHUNK_COUNT).next_power_of_two();
+    chunk_size.clamp(MIN_AUTO_CHUNK_BYTES as u64, MAX_AUTO_CHUNK_BYTES as u64) as usize
+}
+
+/// A builder for a [`File`] with a non default hash algorithm or chunking.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{File, HashAlgorithm};
+///
+/// let data = vec![0u8; 1 << 20];
+/// let file = File::builder()
+///     .hash(HashAlgorithm::Sha512)
+///     .auto_chunk_size(data.len() as u64)
+///     .build(&data)
+///     .unwrap();
+///
+/// assert_eq!(file.chunk_size(), 1024);
+/// assert_eq!(file.get_size(), 1024);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileBuilder {
+    hasher: ChunkHasher,
+}
+
+impl FileBuilder {
+    pub fn hash(mut self, algorithm: HashAlgorithm) -> Self {
+        self.hasher.algorithm = algorithm;
+        self
+    }
+
+    /// Sets the size of chunks in bytes, the default is [`CHUNK_BYTES`].
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.hasher.chunking = Chunking::Fixed(chunk_size);
+        self
+    }
+
+    /// Splits the file in content-defined chunks instead of fixed size ones, see [`CdcParams`].
+    pub fn content_defined(mut self, params: CdcParams) -> Self {
+        self.hasher.chunking = Chunking::ContentDefined(params);
+        self
+    }
+
+    /// Sets the chunk size that is picked by [`chunk_size_for`] for the provided file length.
+    pub fn auto_chunk_size(self, file_len: u64) -> Self {
+        self.chunk_size(chunk_size_for(file_len))
+    }
+
+    pub fn build(self, data: &[u8]) -> Result<File, FileError> {
+        self.validate()?;
+        let data = Bytes::copy_from_slice(data);
+        File::from_chunks(File::to_chunks(&data, &self.hasher.chunking), self.hasher)
+    }
+
+    /// Encrypts every chunk of the data with the key and builds the merkle tree over the
+    /// encrypted chunks, see [`ReadCapability`]. The chunks of the returned file are
+    /// [`crate::ENCRYPTION_OVERHEAD`] bytes longer than the chunks of the plaintext.
+    pub fn build_encrypted(
+        self,
+        data: &[u8],
+        key: EncryptionKey,
+    ) -> Result<(File, ReadCapability), FileError> {
+        self.validate()?;
+        let data = Bytes::copy_from_slice(data);
+        let chunks = File::to_chunks(&data, &self.hasher.chunking)
+            .iter()
+            .map(|chunk| key.encrypt_chunk(chunk))
+            .collect();
+        let hasher = ChunkHasher {
+            chunking: encrypted_chunking(&self.hasher.chunking),
+            ..self.hasher
+        };
+
+        let file = File::from_chunks(chunks, hasher)?;
+        let root = file.get_root()?;
+        Ok((file, ReadCapability { root, key }))
+    }
+
+    /// Starts a file that is still being written, see [`GrowingFile`].
+    pub fn growing(self) -> Result<GrowingFile, FileError> {
+        self.validate()?;
+        Ok(GrowingFile::new(self.hasher))
+    }
+
+    pub async fn from_reader<R>(self, reader: R) -> Result<File, FileError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        self.from_reader_with_progress(reader, &CancellationToken::new(), |_| {})
+            .await
+    }
+
+    /// Like [`FileBuilder::from_reader`], but reports the progress to the callback and returns
+    /// `FileError::Cancelled` once the token is cancelled.
+    ///
+    /// Leaves are hashed while the chunks are read, but the chunks themselves are kept since the
+    /// returned file serves them from memory. Use [`FileBuilder::open_with_progress`] for a file
+    /// on disk that is too large to keep in memory.
+    pub async fn from_reader_with_progress<R, F>(
+        self,
+        reader: R,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Result<File, FileError>
+    where
+        R: AsyncRead + Unpin,
+        F: FnMut(&IngestProgress),
+    {
+        self.validate()?;
+
+        let mut tracker = ProgressTracker::new(None, cancel, progress);
+        let mut chunks = Vec::default();
+        let mut leaf_hashes = Vec::default();
+        let mut len = 0;
+        read_chunks(reader, &self.hasher.chunking, cancel, |c| {
+            leaf_hashes.push(self.hasher.leaf_hash(&c));
+            len += c.len() as u64;
+            tracker.chunk(c.len())?;
+            chunks.push(c);
+            Ok(())
+        })
+        .await?;
+
+        tracker.phase(IngestPhase::BuildingTree)?;
+        let tree = ChunkMerkleTree::from_leaf_hashes(self.hasher, leaf_hashes, len)?;
+        tracker.phase(IngestPhase::Done)?;
+        Ok(File::from_storage(ChunkStorage::Memory(chunks), tree))
+    }
+
+    /// Builds a file that is backed by a file on disk, only the merkle tree is kept in memory
+    /// and the chunks are read on demand.
+    pub async fn open<P: AsRef<Path>>(self, path: P) -> Result<File, FileError> {
+        self.open_with_progress(path, &CancellationToken::new(), |_| {})
+            .await
+    }
+
+    /// Like [`FileBuilder::open`], but reports the progress to the callback and returns
+    /// `FileError::Cancelled` once the token is cancelled.
+    pub async fn open_with_progress<P, F>(
+        self,
+        path: P,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Result<File, FileError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&IngestProgress),
+    {
+        self.validate()?;
+
+        let path = path.as_ref().to_path_buf();
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|_| FileError::File)?;
+        let metadata = file.metadata().await.map_err(|_| FileError::File)?;
+        let mut disk_file = DiskFile::new(path, &metadata, self.hasher.chunk_size());
+
+        let mut tracker = ProgressTracker::new(Some(metadata.len()), cancel, progress);
+        let mut leaf_hashes = Vec::default();
+        let mut offsets = Vec::default();
+        let mut offset = 0;
+        let file = read_chunks(file, &self.hasher.chunking, cancel, |c| {
+            leaf_hashes.push(self.hasher.leaf_hash(&c));
+            offsets.push(offset);
+            offset += c.len() as u64;
+            tracker.chunk(c.len())
+        })
+        .await?;
+
+        // The file could have been modified while its merkle tree was being built.
+        disk_file.check(&file.into_std().await)?;
+
+        if !self.hasher.chunking.is_padded() {
+            disk_file = disk_file.with_offsets(offsets);
+        }
+
+        tracker.phase(IngestPhase::BuildingTree)?;
+        let tree = ChunkMerkleTree::from_leaf_hashes(self.hasher, leaf_hashes, disk_file.len())?;
+        tracker.phase(IngestPhase::Done)?;
+        Ok(File::from_storage(ChunkStorage::Disk(disk_file), tree))
+    }
+
+    /// Builds a file that is backed by a memory mapped file on disk, see [`MmapFile`].
+    pub fn mmap<P: AsRef<Path>>(self, path: P) -> Result<File, FileError> {
+        self.validate()?;
+
+        let mut mmap_file = MmapFile::open(path.as_ref().to_path_buf(), self.hasher.chunk_size())?;
+        let chunks = File::to_chunks(mmap_file.data(), &self.hasher.chunking);
+        let leaf_hashes = chunks.iter().map(|c| self.hasher.leaf_hash(c)).collect();
+
+        if !self.hasher.chunking.is_padded() {
+            let base = mmap_file.data().as_ptr();
+            let offsets = chunks
+                .iter()
+                .map(|c| (c.data.as_ptr() as usize - base as usize) as u64)
+                .collect();
+            mmap_file = mmap_file.with_offsets(offsets);
+        }
+
+        let len = mmap_file.data().len() as u64;
+        let tree = ChunkMerkleTree::from_leaf_hashes(self.hasher, leaf_hashes, len)?;
+        Ok(File::from_storage(ChunkStorage::Mmap(mmap_file), tree))
+    }
+
+    pub(crate) fn hasher(&self) -> ChunkHasher {
+        self.hasher
+    }
+
+    fn validate(&self) -> Result<(), FileError> {
+        self.hasher.chunking.validate()
+    }
+}
+
+/// Reports the progress of an ingestion to its callback and stops it once it's cancelled.
+pub(super) struct ProgressTracker<'a, F> {
+    progress: IngestProgress,
+    cancel: &'a CancellationToken,
+    callback: F,
+}
+
+impl<'a, F: FnMut(&IngestProgress)> ProgressTracker<'a, F> {
+    pub(super) fn new(
+        total_bytes: Option<u64>,
+        cancel: &'a CancellationToken,
+        callback: F,
+    ) -> Self {
+        Self {
+            progress: IngestProgress {
+                phase: IngestPhase::Reading,
+                bytes_read: 0,
+                chunks_hashed: 0,
+                total_bytes,
+            },
+            cancel,
+            callback,
+        }
+    }
+
+    /// Continues the progress of an ingestion that already read a prefix, e.g. after a
+    /// checkpoint.
+    pub(super) fn starting_at(mut self, bytes_read: u64, chunks_hashed: usize) -> Self {
+        self.progress.bytes_read = bytes_read;
+        self.progress.chunks_hashed = chunks_hashed;
+        self
+    }
+
+    pub(super) fn chunk(&mut self, len: usize) -> Result<(), FileError> {
+        self.progress.bytes_read += len as u64;
+        self.progress.chunks_hashed += 1;
+        self.report()
+    }
+
+    pub(super) fn phase(&mut self, phase: IngestPhase) -> Result<(), FileError> {
+        self.progress.phase = phase;
+        self.report()
+    }
+
+    fn report(&mut self) -> Result<(), FileError> {
+        (self.callback)(&self.progress);
+        match self.cancel.is_cancelled() && self.progress.phase != IngestPhase::Done {
+            true => Err(FileError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Reads chunks cut by `chunking` until the reader is exhausted or `f` fails and returns the
+/// reader. Returns `FileError::Cancelled` before the next read once the token is cancelled.
+pub(crate) async fn read_chunks<R, F>(
+    mut reader: R,
+    chunking: &Chunking,
+    cancel: &CancellationToken,
+    mut f: F,
+) -> Result<R, FileError>
+where
+    R: AsyncRead + Unpin,
+    F: FnMut(Chunk) -> Result<(), FileError>,
+{
+    let max_chunk_size = chunking.max_chunk_size();
+    let mut buf = vec![0; max_chunk_size];
+    let mut filled = 0;
+    let mut exhausted = false;
+    let mut leaf_idx = 0;
+
+    loop {
+        // A single read can return less bytes than requested, so keep reading until the buffer
+        // holds the longest possible chunk or the reader is exhausted.
+        while !exhausted && filled < max_chunk_size {
+            if cancel.is_cancelled() {
+                return Err(FileError::Cancelled);
+            }
+            let bytes = reader
+                .read(&mut buf[filled..])
+                .await
+                .map_err(|_| FileError::File)?;
+
+            exhausted = bytes == 0;
+            filled += bytes;
+        }
+
+        if filled == 0 {
+            break;
+        }
+
+        let len = chunking.next_cut(&buf[..filled]);
+        f(Chunk {
+            data: Bytes::copy_from_slice(&buf[..len]),
+            leaf_idx,
+        })?;
+        leaf_idx += 1;
+
+        // Keep the bytes after the cut for the next chunk.
+        buf.copy_within(len..filled, 0);
+        filled -= len;
+    }
+
+    Ok(reader)
+}
+
+mod tests {
+    #[test]
+    fn test_chunk_size_for() {
+        use super::*;
+
+        assert_eq!(chunk_size_for(0), MIN_AUTO_CHUNK_BYTES);
+        assert_eq!(chunk_size_for(100_000), MIN_AUTO_CHUNK_BYTES);
+        assert_eq!(chunk_size_for(2048 * 1024 + 1), 2048);
+        assert_eq!(chunk_size_for(700 * 1024 * 1024), 512 * 1024);
+        assert_eq!(chunk_size_for(u64::MAX / 2), MAX_AUTO_CHUNK_BYTES);
+    }
+
+    #[tokio::test]
+    async fn test_from_reader_with_chunk_size() {
+        use super::*;
+
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let file = File::builder()
+            .chunk_size(2000)
+            .from_reader(&data[..])
+            .await
+            .unwrap();
+        assert_eq!(file.get_size(), 3);
+        assert_eq!(file.get_chunk(2).unwrap().0.data, &data[4000..]);
+
+        let built = File::builder().chunk_size(2000).build(&data).unwrap();
+        assert_eq!(file.get_root().unwrap(), built.get_root().unwrap());
+
+        let res = File::builder().chunk_size(0).build(&data);
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+    }
+
+    #[tokio::test]
+    async fn test_open_with_progress() {
+        use super::*;
+        use std::io::Write;
+
+        let data = vec![7u8; 5000];
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&data).unwrap();
+        let builder = File::builder().chunk_size(2000);
+
+        let mut updates = Vec::new();
+        let cancel = CancellationToken::new();
+        let file = builder
+            .open_with_progress(tmp.path(), &cancel, |p| updates.push(*p))
+            .await
+            .unwrap();
+        assert_eq!(
+            file.get_root().unwrap(),
+            builder.build(&data).unwrap().get_root().unwrap()
+        );
+        let phases: Vec<_> = updates.iter().map(|p| p.phase).collect();
+        assert_eq!(
+            phases,
+            [
+                IngestPhase::Reading,
+                IngestPhase::Reading,
+                IngestPhase::Reading,
+                IngestPhase::BuildingTree,
+                IngestPhase::Done
+            ]
+        );
+        assert_eq!(updates[1].bytes_read, 4000);
+        assert_eq!(updates[1].total_bytes, Some(5000));
+        assert_eq!(updates[4].chunks_hashed, 3);
+
+        // A token that is cancelled up front stops before the first read.
+        cancel.cancel();
+        let mut updates = 0;
+        let res = builder
+            .open_with_progress(tmp.path(), &cancel, |_| updates += 1)
+            .await;
+        assert!(matches!(res, Err(FileError::Cancelled)));
+        assert_eq!(updates, 0);
+        let res = builder
+            .from_reader_with_progress(&data[..], &cancel, |_| updates += 1)
+            .await;
+        assert!(matches!(res, Err(FileError::Cancelled)));
+        assert_eq!(updates, 0);
+    }
+
+    #[tokio::test]
+    async fn test_content_defined() {
+        use super::*;
+        use crate::{root_from_partial, test_data};
+        use std::io::Write;
+
+        let data = test_data(50_000);
+        let params = CdcParams::new(512, 2048, 8192);
+        let builder = File::builder().content_defined(params);
+
+        let file = builder.build(&data).unwrap();
+        let lengths = file.chunk_lengths().unwrap().unwrap();
+        assert_eq!(lengths.iter().sum::<usize>(), data.len());
+        assert_eq!(lengths.len(), file.get_size());
+        assert!(File::new(&data).unwrap().chunk_lengths().unwrap().is_none());
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&data).unwrap();
+        let from_reader = builder.from_reader(&data[..]).await.unwrap();
+        let on_disk = builder.open(tmp.path()).await.unwrap();
+        let mapped = builder.mmap(tmp.path()).unwrap();
+
+        let root = file.get_root().unwrap();
+        for other in [&from_reader, &on_disk, &mapped] {
+            assert_eq!(other.get_root().unwrap(), root);
+            assert_eq!(other.chunk_lengths().unwrap().unwrap(), lengths);
+        }
+
+        // Leaves are not padded, a chunk verifies with its exact length only.
+        let idx = lengths.len() - 1;
+        let (chunk, proof) = on_disk.get_chunk(idx).unwrap();
+        assert_eq!(chunk.len(), lengths[idx]);
+        let (hasher, meta) = (on_disk.hasher(), on_disk.meta());
+        let untrusted_root = root_from_partial(&hasher, &meta, &chunk, idx, proof.clone()).unwrap();
+        assert_eq!(untrusted_root, root);
+
+        let mut padded = chunk.data.to_vec();
+        padded.push(0);
+        let padded = Chunk {
+            data: padded.into(),
+            leaf_idx: idx,
+        };
+        let untrusted_root = root_from_partial(&hasher, &meta, &padded, idx, proof);
+        assert!(!matches!(untrusted_root, Ok(r) if r == root));
+
+        let res = File::builder()
+            .content_defined(CdcParams::new(4096, 1024, 8192))
+            .build(&data);
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+    }
+}
@@ -0,0 +1,496 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncSeekExt;
+
+use super::builder::{read_chunks, ProgressTracker};
+use crate::merkle::MerkleTree;
+use crate::{
+    write_atomic, AnyHash, CancellationToken, Chunk, ChunkHasher, ChunkMerkleTree, ChunkStorage,
+    Chunking, DiskFile, File, FileBuilder, FileError, HashAlgorithm, IngestPhase, IngestProgress,
+};
+
+/// Number of chunks of the prefix that are hashed again when hashing is resumed, besides the
+/// last one.
+const SPOT_CHECKS: usize = 16;
+
+/// The state of a file that is being hashed, written to a sidecar file by
+/// [`FileBuilder::open_checkpointed`] so hashing can be resumed after a crash.
+///
+/// Checkpoints are taken at chunk boundaries, so hashing continues with the same cuts for
+/// content-defined chunking as well.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub algorithm: HashAlgorithm,
+    pub chunking: Chunking,
+    /// Bytes of the input that are hashed.
+    pub position: u64,
+    /// Length and modification time of the file when it was hashed, hashing is only resumed if
+    /// they're unchanged.
+    pub source_len: u64,
+    pub source_modified: Option<SystemTime>,
+    pub leaf_hashes: Vec<AnyHash>,
+    /// Start offsets of the content-defined chunks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offsets: Option<Vec<u64>>,
+    /// Roots of the complete subtrees over the leaf hashes, the largest first. They're checked
+    /// against the leaf hashes when the checkpoint is loaded.
+    pub subtree_roots: Vec<AnyHash>,
+}
+
+impl Checkpoint {
+    fn new(hasher: ChunkHasher) -> Self {
+        Self {
+            algorithm: hasher.algorithm,
+            chunking: hasher.chunking,
+            position: 0,
+            source_len: 0,
+            source_modified: None,
+            leaf_hashes: vec![],
+            offsets: (!hasher.chunking.is_padded()).then(Vec::new),
+            subtree_roots: vec![],
+        }
+    }
+
+    /// Reads a checkpoint, returns `FileError::File` if it can't be read or its subtree roots
+    /// don't match its leaf hashes.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+        let data = fs::read(path).map_err(|_| FileError::File)?;
+        let checkpoint: Self = serde_json::from_slice(&data).map_err(|_| FileError::File)?;
+        if !checkpoint.is_consistent()
+            || checkpoint.subtree_roots
+                != subtree_roots(&checkpoint.hasher(), &checkpoint.leaf_hashes)
+        {
+            return Err(FileError::File);
+        }
+        Ok(checkpoint)
+    }
+
+    /// Returns true if the chunks fit in the hashed prefix.
+    fn is_consistent(&self) -> bool {
+        let count = self.leaf_hashes.len() as u64;
+        match (&self.offsets, self.chunking) {
+            (None, Chunking::Fixed(chunk_size)) => {
+                let chunk_size = chunk_size as u64;
+                self.position <= count * chunk_size
+                    && (count == 0 || self.position > (count - 1) * chunk_size)
+            }
+            (Some(offsets), Chunking::ContentDefined(_)) => {
+                offsets.len() as u64 == count
+                    && offsets.first().is_none_or(|&first| first == 0)
+                    && offsets
+                        .iter()
+                        .chain([&self.position])
+                        .collect::<Vec<_>>()
+                        .windows(2)
+                        .all(|w| w[0] < w[1])
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes the checkpoint to a temporary file first, so a crash while it's written leaves the
+    /// previous checkpoint.
+    fn save(&mut self, path: &Path) -> Result<(), FileError> {
+        self.subtree_roots = subtree_roots(&self.hasher(), &self.leaf_hashes);
+        write_atomic(path, |w| {
+            serde_json::to_writer(w, self).map_err(|_| FileError::File)
+        })
+    }
+
+    fn hasher(&self) -> ChunkHasher {
+        ChunkHasher {
+            algorithm: self.algorithm,
+            chunking: self.chunking,
+        }
+    }
+
+    fn push(&mut self, chunk: &Chunk) {
+        if let Some(offsets) = self.offsets.as_mut() {
+            offsets.push(self.position);
+        }
+        self.leaf_hashes.push(self.hasher().leaf_hash(chunk));
+        self.position += chunk.len() as u64;
+    }
+
+    /// Offset and length of a hashed chunk.
+    fn chunk_range(&self, idx: usize) -> (u64, u64) {
+        let (start, end) = match (&self.offsets, self.chunking) {
+            (Some(offsets), _) => (
+                offsets[idx],
+                offsets.get(idx + 1).copied().unwrap_or(self.position),
+            ),
+            (None, chunking) => {
+                let chunk_size = chunking.max_chunk_size() as u64;
+                let start = idx as u64 * chunk_size;
+                (start, self.position.min(start + chunk_size))
+            }
+        };
+        (start, end - start)
+    }
+
+    /// Compares the length and the modification time of the file with the recorded ones, then
+    /// hashes the last chunk of the prefix and a few chunks spread over it again. Returns
+    /// `FileError::Changed` if anything differs.
+    fn check_prefix(&self, path: &Path) -> Result<(), FileError> {
+        let mut file = fs::File::open(path).map_err(|_| FileError::File)?;
+        let metadata = file.metadata().map_err(|_| FileError::File)?;
+        if metadata.len() != self.source_len
+            || metadata.modified().ok() != self.source_modified
+            || metadata.len() < self.position
+        {
+            return Err(FileError::Changed);
+        }
+
+        let count = self.leaf_hashes.len();
+        let mut indices: Vec<usize> = (0..SPOT_CHECKS).map(|i| i * count / SPOT_CHECKS).collect();
+        indices.push(count.saturating_sub(1));
+        indices.dedup();
+
+        for idx in indices.into_iter().filter(|&idx| idx < count) {
+            let (start, len) = self.chunk_range(idx);
+            let mut data = vec![0; len as usize];
+            file.seek(SeekFrom::Start(start))
+                .map_err(|_| FileError::File)?;
+            file.read_exact(&mut data).map_err(|_| FileError::Changed)?;
+
+            let chunk = Chunk {
+                data: Bytes::from(data),
+                leaf_idx: idx,
+            };
+            if self.hasher().leaf_hash(&chunk) != self.leaf_hashes[idx] {
+                return Err(FileError::Changed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Roots of the complete subtrees that the binary representation of the leaf count splits the
+/// leaves in, the largest first.
+fn subtree_roots(hasher: &ChunkHasher, leaf_hashes: &[AnyHash]) -> Vec<AnyHash> {
+    let mut roots = vec![];
+    let mut start = 0;
+    while start < leaf_hashes.len() {
+        let size = 1 << (leaf_hashes.len() - start).ilog2();
+        let level = leaf_hashes[start..start + size].to_vec();
+        let tree =
+            <ChunkMerkleTree as MerkleTree<Chunk, ChunkHasher>>::build_tree_from_first_level(
+                hasher, level,
+            )
+            .expect("complete subtree");
+        roots.extend(tree.last().cloned());
+        start += size;
+    }
+    roots
+}
+
+impl FileBuilder {
+    /// Same as [`FileBuilder::open`], but the state is written to the `checkpoint` sidecar file
+    /// every `interval` bytes. The sidecar is removed once the file is hashed, after a crash
+    /// hashing continues with [`FileBuilder::resume`].
+    pub async fn open_checkpointed<P, C>(
+        self,
+        path: P,
+        checkpoint: C,
+        interval: u64,
+    ) -> Result<File, FileError>
+    where
+        P: AsRef<Path>,
+        C: AsRef<Path>,
+    {
+        self.open_checkpointed_with_progress(
+            path,
+            checkpoint,
+            interval,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .await
+    }
+
+    /// Like [`FileBuilder::open_checkpointed`], but reports the progress to the callback and
+    /// returns `FileError::Cancelled` once the token is cancelled. The state is written to the
+    /// sidecar before returning, so hashing can be resumed where it was cancelled.
+    pub async fn open_checkpointed_with_progress<P, C, F>(
+        self,
+        path: P,
+        checkpoint: C,
+        interval: u64,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Result<File, FileError>
+    where
+        P: AsRef<Path>,
+        C: AsRef<Path>,
+        F: FnMut(&IngestProgress),
+    {
+        self.hasher().chunking.validate()?;
+        let state = Checkpoint::new(self.hasher());
+        self.hash_from(
+            path.as_ref(),
+            checkpoint.as_ref(),
+            interval,
+            state,
+            cancel,
+            progress,
+        )
+        .await
+    }
+
+    /// Continues hashing from the checkpoint written by [`FileBuilder::open_checkpointed`].
+    ///
+    /// The prefix is checked before hashing continues: the length and the modification time of
+    /// the file must be the recorded ones, and the last hashed chunk and a few chunks spread over
+    /// the prefix are hashed again. Returns `FileError::Changed` if they don't match and `FileError::ChunkSize` if the checkpoint was written with another hash algorithm
+    /// or chunking.
+    pub async fn resume<P, C>(
+        self,
+        path: P,
+        checkpoint: C,
+        interval: u64,
+    ) -> Result<File, FileError>
+    where
+        P: AsRef<Path>,
+        C: AsRef<Path>,
+    {
+        self.resume_with_progress(
+            path,
+            checkpoint,
+            interval,
+            &CancellationToken::new(),
+            |_| {},
+        )
+        .await
+    }
+
+    /// Like [`FileBuilder::resume`], but reports the progress to the callback and returns
+    /// `FileError::Cancelled` once the token is cancelled, see
+    /// [`FileBuilder::open_checkpointed_with_progress`]. The progress starts at the checkpoint.
+    pub async fn resume_with_progress<P, C, F>(
+        self,
+        path: P,
+        checkpoint: C,
+        interval: u64,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Result<File, FileError>
+    where
+        P: AsRef<Path>,
+        C: AsRef<Path>,
+        F: FnMut(&IngestProgress),
+    {
+        let state = Checkpoint::load(checkpoint.as_ref())?;
+        if state.hasher() != self.hasher() {
+            return Err(FileError::ChunkSize);
+        }
+        state.check_prefix(path.as_ref())?;
+
+        self.hash_from(
+            path.as_ref(),
+            checkpoint.as_ref(),
+            interval,
+            state,
+            cancel,
+            progress,
+        )
+        .await
+    }
+
+    async fn hash_from<F: FnMut(&IngestProgress)>(
+        self,
+        path: &Path,
+        checkpoint: &Path,
+        interval: u64,
+        mut state: Checkpoint,
+        cancel: &CancellationToken,
+        progress: F,
+    ) -> Result<File, FileError> {
+        let hasher = self.hasher();
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .map_err(|_| FileError::File)?;
+        let metadata = file.metadata().await.map_err(|_| FileError::File)?;
+        let mut disk_file = DiskFile::new(path.to_path_buf(), &metadata, hasher.chunk_size());
+        state.source_len = metadata.len();
+        state.source_modified = metadata.modified().ok();
+        file.seek(SeekFrom::Start(state.position))
+            .await
+            .map_err(|_| FileError::File)?;
+
+        let mut tracker = ProgressTracker::new(Some(metadata.len()), cancel, progress)
+            .starting_at(state.position, state.leaf_hashes.len());
+        let mut next = state.position + interval;
+        let res = read_chunks(file, &hasher.chunking, cancel, |c| {
+            state.push(&c);
+            if state.position >= next {
+                state.save(checkpoint)?;
+                next = state.position + interval;
+            }
+            tracker.chunk(c.len())
+        })
+        .await
+        .and_then(|file| {
+            tracker.phase(IngestPhase::BuildingTree)?;
+            Ok(file)
+        });
+        let file = match res {
+            Err(FileError::Cancelled) => {
+                state.save(checkpoint)?;
+                return Err(FileError::Cancelled);
+            }
+            res => res?,
+        };
+
+        // The file could have been modified while its merkle tree was being built.
+        disk_file.check(&file.into_std().await)?;
+
+        if let Some(offsets) = state.offsets {
+            disk_file = disk_file.with_offsets(offsets);
+        }
+        let tree = ChunkMerkleTree::from_leaf_hashes(hasher, state.leaf_hashes, disk_file.len())?;
+        if checkpoint.exists() {
+            fs::remove_file(checkpoint).map_err(|_| FileError::File)?;
+        }
+        tracker.phase(IngestPhase::Done)?;
+
+        Ok(File::from_storage(ChunkStorage::Disk(disk_file), tree))
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_resume() {
+        use super::*;
+        use crate::{test_cdc_params, test_data};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image");
+        let sidecar = dir.path().join("image.checkpoint");
+        let data = test_data(50_000);
+        fs::write(&path, &data).unwrap();
+
+        let builders = [
+            File::builder().chunk_size(1000),
+            File::builder().content_defined(test_cdc_params()),
+        ];
+        for builder in builders {
+            let expected = builder.open(&path).await.unwrap().get_root().unwrap();
+            let file = builder
+                .open_checkpointed(&path, &sidecar, 4096)
+                .await
+                .unwrap();
+            assert_eq!(file.get_root().unwrap(), expected);
+            assert!(!sidecar.exists());
+
+            // A crash after a part of the file was hashed.
+            let modified = fs::metadata(&path).unwrap().modified().unwrap();
+            let mut state = Checkpoint::new(builder.hasher());
+            state.source_len = data.len() as u64;
+            state.source_modified = Some(modified);
+            for chunk in File::to_chunks(&Bytes::from(data.clone()), &builder.hasher().chunking)
+                .iter()
+                .take(20)
+            {
+                state.push(chunk);
+            }
+            state.save(&sidecar).unwrap();
+            assert_eq!(Checkpoint::load(&sidecar).unwrap(), state);
+
+            let file = builder.resume(&path, &sidecar, 4096).await.unwrap();
+            assert_eq!(file.get_root().unwrap(), expected);
+            assert_eq!(
+                file.get_chunk(30).unwrap().0.data.len(),
+                file.chunk_lengths().unwrap().map_or(1000, |l| l[30])
+            );
+            assert!(!sidecar.exists());
+
+            // The file was written again since the checkpoint, even with the same bytes.
+            state.save(&sidecar).unwrap();
+            fs::write(&path, &data).unwrap();
+            let res = builder.resume(&path, &sidecar, 4096).await;
+            assert!(matches!(res, Err(FileError::Changed)));
+
+            // The prefix changed, but the length and the modification time were kept.
+            let mut changed = data.clone();
+            changed[0] ^= 1;
+            fs::write(&path, &changed).unwrap();
+            let set_modified = |modified| {
+                let file = fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(modified).unwrap();
+            };
+            set_modified(modified);
+            let res = builder.resume(&path, &sidecar, 4096).await;
+            assert!(matches!(res, Err(FileError::Changed)));
+            fs::write(&path, &data).unwrap();
+            set_modified(modified);
+            assert!(builder.resume(&path, &sidecar, 4096).await.is_ok());
+            state.save(&sidecar).unwrap();
+
+            let other = builder.hash(HashAlgorithm::Sha512);
+            let res = other.resume(&path, &sidecar, 4096).await;
+            assert!(matches!(res, Err(FileError::ChunkSize)));
+            fs::remove_file(&sidecar).unwrap();
+        }
+
+        // A sidecar whose leaf hashes don't match its subtree roots is rejected.
+        let mut state = Checkpoint::new(ChunkHasher::new(HashAlgorithm::Sha256, 1000));
+        state.push(&Chunk {
+            data: Bytes::from_static(b"chunk"),
+            leaf_idx: 0,
+        });
+        state.save(&sidecar).unwrap();
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&fs::read(&sidecar).unwrap()).unwrap();
+        json["subtree_roots"] = serde_json::json!([]);
+        fs::write(&sidecar, json.to_string()).unwrap();
+        assert!(Checkpoint::load(&sidecar).is_err());
+        state.position = 1001;
+        state.save(&sidecar).unwrap();
+        assert!(Checkpoint::load(&sidecar).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_checkpointed() {
+        use super::*;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image");
+        let sidecar = dir.path().join("image.checkpoint");
+        let data: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &data).unwrap();
+        let builder = File::builder().chunk_size(1000);
+
+        // Cancelling writes the state, so hashing resumes after the last hashed chunk.
+        let cancel = CancellationToken::new();
+        let res = builder
+            .open_checkpointed_with_progress(&path, &sidecar, 1 << 20, &cancel, |p| {
+                if p.bytes_read == 20_000 {
+                    cancel.cancel();
+                }
+            })
+            .await;
+        assert!(matches!(res, Err(FileError::Cancelled)));
+        assert_eq!(Checkpoint::load(&sidecar).unwrap().position, 20_000);
+
+        let mut updates = Vec::new();
+        let file = builder
+            .resume_with_progress(&path, &sidecar, 1 << 20, &CancellationToken::new(), |p| {
+                updates.push(*p)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            file.get_root().unwrap(),
+            builder.build(&data).unwrap().get_root().unwrap()
+        );
+        assert_eq!(updates[0].bytes_read, 21_000);
+        assert_eq!(updates[0].chunks_hashed, 21);
+        assert_eq!(updates.last().unwrap().phase, IngestPhase::Done);
+        assert!(!sidecar.exists());
+    }
+}
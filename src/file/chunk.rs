@@ -1,10 +1,15 @@
-use serde::Serializer;
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serializer};
 
 use crate::AsBytes;
 
+/// A piece of a file together with its leaf index in the merkle tree.
+///
+/// The data is a reference counted buffer, so cloning a chunk (or slicing it out of a buffer
+/// that holds the whole file) doesn't copy the bytes.
 #[derive(Clone, Debug)]
 pub struct Chunk {
-    pub data: Vec<u8>,
+    pub data: Bytes,
     pub leaf_idx: usize,
 }
 
@@ -28,6 +33,22 @@ impl serde::Serialize for Chunk {
     }
 }
 
+/// The leaf index is not a part of the serialized chunk, so a deserialized chunk has `leaf_idx`
+/// set to 0 and the index has to be taken from the request that returned it.
+impl<'de> Deserialize<'de> for Chunk {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        let data = base64::decode(&s).map_err(serde::de::Error::custom)?;
+        Ok(Chunk {
+            data: data.into(),
+            leaf_idx: 0,
+        })
+    }
+}
+
 impl AsBytes for Chunk {
     fn as_bytes(&self) -> &[u8] {
         &self.data
@@ -0,0 +1,225 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{FileError, CHUNK_BYTES};
+
+/// How a file is split in chunks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chunking {
+    /// Chunks of the same size, only the last chunk can be shorter and it is padded with zero
+    /// bytes before it is hashed.
+    Fixed(usize),
+
+    /// Variable length chunks that are cut where the content matches, see [`CdcParams`]. The
+    /// chunks are hashed as they are, without padding.
+    ContentDefined(CdcParams),
+}
+
+impl Chunking {
+    /// The longest chunk that can be produced.
+    pub fn max_chunk_size(&self) -> usize {
+        match self {
+            Self::Fixed(chunk_size) => *chunk_size,
+            Self::ContentDefined(params) => params.max,
+        }
+    }
+
+    /// Returns true if the leaves are padded to the chunk size before they are hashed.
+    pub fn is_padded(&self) -> bool {
+        matches!(self, Self::Fixed(_))
+    }
+
+    /// Returns the length of the chunk at the start of `data`.
+    ///
+    /// The cut only depends on the first [`Chunking::max_chunk_size`] bytes, so a reader has to
+    /// buffer that many bytes (or everything that's left) before calling it.
+    pub fn next_cut(&self, data: &[u8]) -> usize {
+        match self {
+            Self::Fixed(chunk_size) => data.len().min(*chunk_size),
+            Self::ContentDefined(params) => params.next_cut(data),
+        }
+    }
+
+    /// Fixed length encoding of the chunking that is committed into roots.
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let fields = match self {
+            Self::Fixed(chunk_size) => [0, *chunk_size as u64, 0, 0],
+            Self::ContentDefined(p) => [1, p.min as u64, p.avg as u64, p.max as u64],
+        };
+        fields.iter().flat_map(|n| n.to_be_bytes()).collect()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), FileError> {
+        match self {
+            Self::Fixed(0) => Err(FileError::ChunkSize),
+            Self::Fixed(_) => Ok(()),
+            Self::ContentDefined(params) => params.validate(),
+        }
+    }
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Self::Fixed(CHUNK_BYTES)
+    }
+}
+
+/// Parameters of the FastCDC content-defined chunker.
+///
+/// A gear rolling hash runs over the data and a chunk is cut where the hash matches a mask, so
+/// inserting or removing bytes only changes the chunks around the edit. Normalized chunking is
+/// used: a stricter mask before `avg` bytes and a looser one after it keep most of the chunks
+/// close to the average size, no chunk is shorter than `min` (except the last one) or longer
+/// than `max`.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{CdcParams, File};
+///
+/// let data: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+/// let mut edited = data.clone();
+/// edited.insert(0, 42);
+///
+/// let params = CdcParams::new(1024, 4096, 16384);
+/// let file = File::builder().content_defined(params).build(&data).unwrap();
+/// let edited = File::builder().content_defined(params).build(&edited).unwrap();
+///
+/// // Only the first chunk differs.
+/// let lengths = file.chunk_lengths().unwrap().unwrap();
+/// let edited_lengths = edited.chunk_lengths().unwrap().unwrap();
+/// assert_eq!(edited_lengths[0], lengths[0] + 1);
+/// assert_eq!(edited_lengths[1..], lengths[1..]);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CdcParams {
+    pub min: usize,
+    pub avg: usize,
+    pub max: usize,
+}
+
+impl CdcParams {
+    pub fn new(min: usize, avg: usize, max: usize) -> Self {
+        Self { min, avg, max }
+    }
+
+    fn validate(&self) -> Result<(), FileError> {
+        if self.min == 0 || self.min > self.avg || self.avg > self.max {
+            return Err(FileError::ChunkSize);
+        }
+        Ok(())
+    }
+
+    fn next_cut(&self, data: &[u8]) -> usize {
+        let len = data.len().min(self.max);
+        if len <= self.min {
+            return len;
+        }
+
+        let bits = usize::BITS - 1 - self.avg.leading_zeros();
+        let mask_small = mask(bits + 1);
+        let mask_large = mask(bits.saturating_sub(1));
+        let normal = self.avg.min(len);
+
+        let mut hash = 0u64;
+        for (i, b) in data.iter().enumerate().take(len).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+            let mask = if i < normal { mask_small } else { mask_large };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        len
+    }
+}
+
+impl Default for CdcParams {
+    fn default() -> Self {
+        Self::new(2 * 1024, 8 * 1024, 64 * 1024)
+    }
+}
+
+/// A mask with the highest `bits` bits set, the high bits of the gear hash depend on the last 64
+/// bytes while the low bits only depend on the last few.
+fn mask(bits: u32) -> u64 {
+    match bits {
+        0 => 0,
+        _ => u64::MAX << (64 - bits.min(64)),
+    }
+}
+
+/// Random values for every byte, generated with splitmix64 from a fixed seed so the cut points
+/// never change between builds.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state = 0x706d_746f_7272_656eu64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// Pseudo-random bytes that content-defined chunking cuts at varying lengths, shared by the
+/// tests of every module.
+#[cfg(test)]
+pub(crate) fn test_data(len: u32) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i.wrapping_mul(2654435761) >> 9) as u8)
+        .collect()
+}
+
+/// Small content-defined chunking parameters, so the [`test_data`] of a test is cut in many
+/// chunks.
+#[cfg(test)]
+pub(crate) fn test_cdc_params() -> CdcParams {
+    CdcParams::new(256, 1024, 4096)
+}
+
+mod tests {
+    #[test]
+    fn test_cut_bounds() {
+        use super::*;
+
+        let params = CdcParams::new(1024, 4096, 8192);
+        let data = test_data(200_000);
+
+        let mut offset = 0;
+        let mut lengths = vec![];
+        while offset < data.len() {
+            let cut = params.next_cut(&data[offset..]);
+            lengths.push(cut);
+            offset += cut;
+        }
+
+        let (last, rest) = lengths.split_last().unwrap();
+        assert!(*last <= 8192);
+        assert!(rest.iter().all(|l| (1024..=8192).contains(l)));
+        assert!(rest.iter().any(|l| *l < 8192), "no content-defined cuts");
+
+        // Data without any variation is cut at the maximum size.
+        assert_eq!(params.next_cut(&[0u8; 20_000]), 8192);
+        assert_eq!(params.next_cut(&[0u8; 500]), 500);
+    }
+
+    #[test]
+    fn test_validate() {
+        use super::*;
+
+        assert!(Chunking::ContentDefined(CdcParams::default())
+            .validate()
+            .is_ok());
+        assert!(Chunking::ContentDefined(CdcParams::new(0, 1, 2))
+            .validate()
+            .is_err());
+        assert!(Chunking::ContentDefined(CdcParams::new(4, 2, 8))
+            .validate()
+            .is_err());
+        assert!(Chunking::Fixed(0).validate().is_err());
+    }
+}
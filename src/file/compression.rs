@@ -0,0 +1,129 @@
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use bytes::Bytes;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::FileError;
+
+/// Encoding of chunk data in a [`crate::ChunkStore`] or a [`crate::Piece`].
+///
+/// Compression never changes a root: leaf hashes always cover the uncompressed bytes, so a chunk
+/// is decompressed before it's verified.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// Raw deflate stream (RFC 1951) without a zlib or gzip header.
+    Deflate,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::None, Compression::Deflate];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// Compresses the data, `None` returns it as it is.
+    pub fn compress(&self, data: &[u8]) -> Bytes {
+        match self {
+            Self::None => Bytes::copy_from_slice(data),
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder
+                    .write_all(data)
+                    .and_then(|_| encoder.finish())
+                    .expect("compress into memory")
+                    .into()
+            }
+        }
+    }
+
+    /// Compresses the data, but only if it gets smaller. Returns the encoding that was used.
+    pub fn compress_smaller(&self, data: &[u8]) -> (Bytes, Compression) {
+        match self.compress(data) {
+            compressed if *self != Self::None && compressed.len() < data.len() => {
+                (compressed, *self)
+            }
+            _ => (Bytes::copy_from_slice(data), Self::None),
+        }
+    }
+
+    /// Decompresses the data. Returns `FileError::Encoding` if it's not valid or if it expands
+    /// to more than `max_len` bytes, so a small piece can't be inflated without bound.
+    pub fn decompress(&self, data: &Bytes, max_len: usize) -> Result<Bytes, FileError> {
+        match self {
+            Self::None if data.len() <= max_len => Ok(data.clone()),
+            Self::None => Err(FileError::Encoding),
+            Self::Deflate => {
+                let mut out = Vec::new();
+                DeflateDecoder::new(&data[..])
+                    .take(max_len as u64 + 1)
+                    .read_to_end(&mut out)
+                    .map_err(|_| FileError::Encoding)?;
+
+                if out.len() > max_len {
+                    return Err(FileError::Encoding);
+                }
+                Ok(out.into())
+            }
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = FileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+            .ok_or(FileError::Encoding)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_round_trip() {
+        use super::*;
+
+        let data: Bytes = [b"pmtorrent ".repeat(100), vec![0u8; 1000]].concat().into();
+        for compression in Compression::ALL {
+            let (encoded, used) = compression.compress_smaller(&data);
+            assert_eq!(used, compression);
+            assert_eq!(used.decompress(&encoded, data.len()).unwrap(), data);
+        }
+
+        // Data that doesn't compress is kept as it is.
+        let (encoded, used) = Compression::Deflate.compress_smaller(b"x");
+        assert_eq!((&encoded[..], used), (&b"x"[..], Compression::None));
+    }
+
+    #[test]
+    fn test_decompress_limits() {
+        use super::*;
+
+        let encoded = Compression::Deflate.compress(&[0u8; 100_000]);
+        assert!(encoded.len() < 1000);
+        assert!(matches!(
+            Compression::Deflate.decompress(&encoded, 99_999),
+            Err(FileError::Encoding)
+        ));
+        assert!(Compression::None.decompress(&encoded, 10).is_err());
+
+        let garbage = Bytes::from_static(&[0xff; 16]);
+        assert!(Compression::Deflate.decompress(&garbage, 1024).is_err());
+        assert_eq!(
+            "DEFLATE".parse::<Compression>().unwrap(),
+            Compression::Deflate
+        );
+        assert!("zip".parse::<Compression>().is_err());
+    }
+}
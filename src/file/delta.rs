@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AnyHash, Chunk, ChunkStorage, File, FileError, FileMeta, MerkleError};
+
+/// The difference between two versions of a file: the chunks of the new version that changed,
+/// see [`File::changed_leaves`].
+///
+/// A delta references the root of the previous version, so it's all a host of the previous
+/// version needs to build the new one with [`FileDelta::apply`].
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{File, FileDelta};
+///
+/// let old = vec![1u8; 10_000];
+/// let mut new = old.clone();
+/// new[5000] = 2;
+/// new.extend_from_slice(&[3u8; 100]);
+///
+/// let (old, new) = (File::new(&old).unwrap(), File::new(&new).unwrap());
+/// assert_eq!(old.changed_leaves(&new).unwrap(), vec![4, 9]);
+///
+/// let delta = FileDelta::between(&old, &new).unwrap();
+/// assert_eq!(delta.chunks.len(), 2);
+/// assert_eq!(delta.apply(&old).unwrap().get_root().unwrap(), delta.root);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileDelta {
+    /// Root of the previous version.
+    pub base: AnyHash,
+    /// Root of the new version.
+    pub root: AnyHash,
+    pub meta: FileMeta,
+    /// The changed chunks and the chunks that the previous version doesn't have.
+    pub chunks: Vec<Chunk>,
+}
+
+impl FileDelta {
+    /// Collects the chunks of `file` that differ from `base`.
+    pub fn between(base: &File, file: &File) -> Result<Self, FileError> {
+        let chunks = base
+            .changed_leaves(file)?
+            .into_iter()
+            .filter(|&idx| idx < file.get_size())
+            .map(|idx| file.storage().get(idx))
+            .collect::<Result<Vec<Chunk>, FileError>>()?;
+
+        Ok(Self {
+            base: base.get_root()?,
+            root: file.get_root()?,
+            meta: file.meta(),
+            chunks,
+        })
+    }
+
+    /// Builds the new version on top of the previous one. Only the changed chunks are hashed,
+    /// the other chunks are shared with `base` together with their leaf hashes and unchanged
+    /// subtrees, see [`crate::ChunkMerkleTree::with_leaf_hashes`].
+    ///
+    /// Returns `FileError::Proof` if `base` isn't the previous version or the result doesn't have
+    /// the root of the new version, `FileError::ChunkSize` if a chunk doesn't have a valid length.
+    pub fn apply(&self, base: &File) -> Result<File, FileError> {
+        if base.get_root()? != self.base {
+            return Err(FileError::Proof);
+        }
+
+        let mut changed: Vec<Option<&Chunk>> = vec![None; self.meta.chunk_count];
+        for chunk in &self.chunks {
+            let slot = changed
+                .get_mut(chunk.leaf_idx)
+                .ok_or(FileError::Merkle(MerkleError::InvalidIdx))?;
+            *slot = Some(chunk);
+        }
+
+        let hasher = base.hasher();
+        let base_hashes = base.tree().leaf_hashes();
+        let mut chunks = Vec::with_capacity(self.meta.chunk_count);
+        let mut leaf_hashes = Vec::with_capacity(self.meta.chunk_count);
+        for (idx, chunk) in changed.into_iter().enumerate() {
+            let (chunk, leaf_hash) = match chunk {
+                Some(chunk) => (chunk.clone(), hasher.leaf_hash(chunk)),
+                None if idx < base_hashes.len() => {
+                    (base.storage().get(idx)?, base_hashes[idx].clone())
+                }
+                None => return Err(FileError::Incomplete),
+            };
+            if !self.meta.chunk_len(&hasher, idx)?.contains(&chunk.len()) {
+                return Err(FileError::ChunkSize);
+            }
+
+            chunks.push(chunk);
+            leaf_hashes.push(leaf_hash);
+        }
+        if chunks.iter().map(|c| c.len() as u64).sum::<u64>() != self.meta.len {
+            return Err(FileError::ChunkSize);
+        }
+
+        let tree = base.tree().with_leaf_hashes(leaf_hashes, self.meta.len)?;
+        let file = File::from_storage(ChunkStorage::Memory(chunks), tree);
+        if file.get_root()? != self.root {
+            return Err(FileError::Proof);
+        }
+        Ok(file)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_file_delta() {
+        use super::*;
+        use crate::{test_cdc_params, test_data, HashAlgorithm};
+
+        // The old last chunk becomes a full chunk and the tree grows from 8 to 16 leaves.
+        let old: Vec<u8> = (0..7500u32).map(|i| (i % 253) as u8).collect();
+        let mut new = old.clone();
+        new[100] ^= 1;
+        new.resize(9000, 5);
+        let builder = File::builder().hash(HashAlgorithm::Sha512);
+        let (old, new) = (builder.build(&old).unwrap(), builder.build(&new).unwrap());
+
+        let delta = FileDelta::between(&old, &new).unwrap();
+        let leaves: Vec<usize> = delta.chunks.iter().map(|c| c.leaf_idx).collect();
+        assert_eq!(leaves, vec![0, 7, 8]);
+        assert_eq!(delta.apply(&old).unwrap().get_root().unwrap(), delta.root);
+
+        // Shrinking back only lists the chunks that differ, dropped chunks have no data.
+        let back = FileDelta::between(&new, &old).unwrap();
+        assert_eq!(new.changed_leaves(&old).unwrap(), vec![0, 7, 8]);
+        assert_eq!(back.chunks.len(), 2);
+        assert_eq!(back.apply(&new).unwrap().get_root().unwrap(), back.root);
+        assert!(matches!(back.apply(&old), Err(FileError::Proof)));
+
+        // A delta that misses a changed chunk doesn't build the new root.
+        let mut incomplete = delta.clone();
+        incomplete.chunks.remove(0);
+        assert!(matches!(incomplete.apply(&old), Err(FileError::Proof)));
+        incomplete.chunks.pop();
+        assert!(matches!(incomplete.apply(&old), Err(FileError::Incomplete)));
+
+        // Content-defined chunks are compared by their leaf hashes as well.
+        let data = test_data(20_000);
+        let mut edited = data.clone();
+        edited[15_000] ^= 1;
+        let builder = File::builder().content_defined(test_cdc_params());
+        let (old, new) = (
+            builder.build(&data).unwrap(),
+            builder.build(&edited).unwrap(),
+        );
+        let delta = FileDelta::between(&old, &new).unwrap();
+        assert_eq!(delta.chunks.len(), 1);
+        assert_eq!(delta.apply(&old).unwrap().get_root().unwrap(), delta.root);
+        assert!(old.changed_leaves(&File::new(&data).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bytes::Bytes;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, MAX_TAG_LEN, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::{digest, hkdf, hmac};
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_hex, encode_hex, AnyHash, CdcParams, Chunk, Chunking, File, FileError};
+
+/// Bytes added to every encrypted chunk: the nonce in front and the authentication tag behind.
+pub const ENCRYPTION_OVERHEAD: usize = NONCE_LEN + MAX_TAG_LEN;
+
+const CONVERGENT_DOMAIN: &[u8] = b"pmtorrent-convergent-key-v1";
+
+/// HKDF labels of the subkeys that derive the nonces and encrypt the chunks, so the key is never
+/// used for both.
+const SUBKEY_SALT: &[u8] = b"pmtorrent-chunk-subkeys-v1";
+const NONCE_INFO: &[u8] = b"pmtorrent-chunk-nonce";
+const AEAD_INFO: &[u8] = b"pmtorrent-chunk-aead";
+
+/// An AES-256-GCM key that encrypts every chunk of a file.
+///
+/// The key is either derived from the content of the file ([`EncryptionKey::convergent`]), so
+/// identical files are encrypted to identical chunks and roots, or a per-file secret. Encryption
+/// is deterministic in both cases: the nonce of a chunk is an HMAC of its index and data, so
+/// rebuilding a file gives the same root and a nonce is never reused for other data. The HMAC and
+/// AES keys are separate subkeys derived from the key with HKDF.
+#[derive(Clone, PartialEq, Eq)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(key: [u8; 32]) -> Self {
+        Self(key)
+    }
+
+    /// Generates a random per-file secret.
+    pub fn generate() -> Result<Self, FileError> {
+        let mut key = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| FileError::File)?;
+        Ok(Self(key))
+    }
+
+    /// Derives the key from the whole content of a file.
+    pub fn convergent(data: &[u8]) -> Self {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(CONVERGENT_DOMAIN);
+        ctx.update(data);
+
+        let mut key = [0u8; 32];
+        key.copy_from_slice(ctx.finish().as_ref());
+        Self(key)
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(&self.0)
+    }
+
+    /// Encrypts a chunk to `nonce || ciphertext || tag`, the leaf index is authenticated as well,
+    /// so a chunk can't be decrypted at another index.
+    pub fn encrypt_chunk(&self, chunk: &Chunk) -> Chunk {
+        let idx = (chunk.leaf_idx as u64).to_be_bytes();
+        let mac = hmac::sign(&self.nonce_key(), &[&idx[..], &chunk.data].concat());
+        let nonce: [u8; NONCE_LEN] = mac.as_ref()[..NONCE_LEN].try_into().expect("nonce");
+
+        let mut sealed = Vec::with_capacity(chunk.len() + ENCRYPTION_OVERHEAD);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&chunk.data);
+        let mut in_out = sealed.split_off(NONCE_LEN);
+        self.aead_key()
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(idx),
+                &mut in_out,
+            )
+            .expect("chunk fits in AES-GCM");
+        sealed.append(&mut in_out);
+
+        Chunk {
+            data: sealed.into(),
+            leaf_idx: chunk.leaf_idx,
+        }
+    }
+
+    /// Decrypts a chunk encrypted by [`EncryptionKey::encrypt_chunk`]. Returns
+    /// `FileError::Decryption` if it was encrypted with another key, for another index or
+    /// modified.
+    pub fn decrypt_chunk(&self, chunk: &Chunk) -> Result<Chunk, FileError> {
+        if chunk.len() < ENCRYPTION_OVERHEAD {
+            return Err(FileError::Decryption);
+        }
+
+        let idx = (chunk.leaf_idx as u64).to_be_bytes();
+        let (nonce, sealed) = chunk.data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| FileError::Decryption)?;
+        let mut in_out = sealed.to_vec();
+        let len = self
+            .aead_key()
+            .open_in_place(nonce, Aad::from(idx), &mut in_out)
+            .map_err(|_| FileError::Decryption)?
+            .len();
+        in_out.truncate(len);
+
+        Ok(Chunk {
+            data: in_out.into(),
+            leaf_idx: chunk.leaf_idx,
+        })
+    }
+
+    fn subkeys(&self) -> hkdf::Prk {
+        hkdf::Salt::new(hkdf::HKDF_SHA256, SUBKEY_SALT).extract(&self.0)
+    }
+
+    fn nonce_key(&self) -> hmac::Key {
+        self.subkeys()
+            .expand(&[NONCE_INFO], hmac::HMAC_SHA256)
+            .expect("HMAC key length")
+            .into()
+    }
+
+    fn aead_key(&self) -> LessSafeKey {
+        let key: UnboundKey = self
+            .subkeys()
+            .expand(&[AEAD_INFO], &AES_256_GCM)
+            .expect("256 bit key")
+            .into();
+        LessSafeKey::new(key)
+    }
+}
+
+/// The key is never printed.
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+impl FromStr for EncryptionKey {
+    type Err = FileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = decode_hex(s).ok_or(FileError::Decryption)?;
+        Ok(Self(key.try_into().map_err(|_| FileError::Decryption)?))
+    }
+}
+
+impl Serialize for EncryptionKey {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for EncryptionKey {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        s.parse()
+            .map_err(|_| serde::de::Error::custom("invalid encryption key"))
+    }
+}
+
+/// The chunking of an encrypted file, every chunk is [`ENCRYPTION_OVERHEAD`] bytes longer than
+/// the plaintext chunk.
+pub fn encrypted_chunking(chunking: &Chunking) -> Chunking {
+    match chunking {
+        Chunking::Fixed(chunk_size) => Chunking::Fixed(chunk_size + ENCRYPTION_OVERHEAD),
+        Chunking::ContentDefined(p) => Chunking::ContentDefined(CdcParams::new(
+            p.min + ENCRYPTION_OVERHEAD,
+            p.avg + ENCRYPTION_OVERHEAD,
+            p.max + ENCRYPTION_OVERHEAD,
+        )),
+    }
+}
+
+/// What a reader of an encrypted file needs: the root of the encrypted file, which mirrors serve
+/// and verify pieces against, and the key to decrypt its chunks.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{EncryptionKey, File, FileRepo, PartialFile};
+///
+/// let data = b"top secret ".repeat(500);
+/// let (file, capability) = File::builder()
+///     .build_encrypted(&data, EncryptionKey::convergent(&data))
+///     .unwrap();
+/// let (pieces, len, chunk_size) = (file.get_size(), file.byte_len(), file.chunk_size());
+///
+/// // A mirror only has the encrypted chunks, but it can still prove them.
+/// let mut mirror = FileRepo::default();
+/// mirror.add(file).unwrap();
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let root = capability.root.clone();
+/// let mut partial = PartialFile::create(dir.path().join("out"), root.clone(), len, chunk_size)
+///     .unwrap();
+/// for idx in 0..pieces {
+///     let piece = mirror.get_piece(root.to_hex(), idx).unwrap();
+///     partial.add_piece(idx, &piece).unwrap();
+/// }
+///
+/// let encrypted = std::fs::read(partial.finish().unwrap()).unwrap();
+/// let encrypted = File::builder().chunk_size(chunk_size).build(&encrypted).unwrap();
+/// assert_eq!(capability.decrypt_file(&encrypted).unwrap(), data);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadCapability {
+    pub root: AnyHash,
+    pub key: EncryptionKey,
+}
+
+impl ReadCapability {
+    /// Decrypts the chunk at `idx`, e.g. the content of a verified piece.
+    pub fn decrypt_chunk(&self, idx: usize, data: &Bytes) -> Result<Bytes, FileError> {
+        let chunk = Chunk {
+            data: data.clone(),
+            leaf_idx: idx,
+        };
+        Ok(self.key.decrypt_chunk(&chunk)?.data)
+    }
+
+    /// Decrypts all chunks of the encrypted file. Returns `FileError::Proof` if the file doesn't
+    /// have the root of the capability.
+    pub fn decrypt_file(&self, file: &File) -> Result<Vec<u8>, FileError> {
+        if file.get_root()? != self.root {
+            return Err(FileError::Proof);
+        }
+
+        let mut data = Vec::with_capacity(file.byte_len() as usize);
+        for idx in 0..file.get_size() {
+            let chunk = file.storage().get(idx)?;
+            data.extend_from_slice(&self.key.decrypt_chunk(&chunk)?.data);
+        }
+        Ok(data)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_encrypt_chunk() {
+        use super::*;
+
+        let key = EncryptionKey::convergent(b"content");
+        let chunk = Chunk {
+            data: Bytes::from_static(b"hello"),
+            leaf_idx: 3,
+        };
+        let encrypted = key.encrypt_chunk(&chunk);
+        assert_eq!(encrypted.len(), 5 + ENCRYPTION_OVERHEAD);
+        assert_eq!(encrypted.data, key.encrypt_chunk(&chunk).data);
+        assert_eq!(key.decrypt_chunk(&encrypted).unwrap().data, chunk.data);
+
+        // The same data at another index is encrypted with another nonce.
+        let moved = Chunk {
+            leaf_idx: 4,
+            ..chunk.clone()
+        };
+        assert_ne!(key.encrypt_chunk(&moved).data, encrypted.data);
+        let swapped = Chunk {
+            leaf_idx: 4,
+            ..encrypted.clone()
+        };
+        assert!(matches!(
+            key.decrypt_chunk(&swapped),
+            Err(FileError::Decryption)
+        ));
+
+        let mut tampered = encrypted.data.to_vec();
+        tampered[NONCE_LEN] ^= 1;
+        let tampered = Chunk {
+            data: tampered.into(),
+            leaf_idx: 3,
+        };
+        assert!(key.decrypt_chunk(&tampered).is_err());
+
+        // The nonce isn't an HMAC with the key itself, the key is only used to derive subkeys.
+        let idx = 3u64.to_be_bytes();
+        let mac = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, &key.0),
+            &[&idx[..], &chunk.data].concat(),
+        );
+        assert_ne!(&encrypted.data[..NONCE_LEN], &mac.as_ref()[..NONCE_LEN]);
+
+        let other = EncryptionKey::generate().unwrap();
+        assert!(other.decrypt_chunk(&encrypted).is_err());
+        assert_eq!(other.to_hex().parse::<EncryptionKey>().unwrap(), other);
+    }
+
+    #[test]
+    fn test_encrypted_file() {
+        use super::*;
+        use crate::{root_from_partial, test_cdc_params, test_data};
+
+        let data = test_data(20_000);
+        let builder = File::builder().content_defined(test_cdc_params());
+        let plain = builder.build(&data).unwrap();
+
+        let key = EncryptionKey::convergent(&data);
+        let (file, capability) = builder.build_encrypted(&data, key.clone()).unwrap();
+        let (again, _) = builder.build_encrypted(&data, key).unwrap();
+        assert_eq!(again.get_root().unwrap(), capability.root);
+        assert_eq!(file.get_size(), plain.get_size());
+        assert_ne!(capability.root, plain.get_root().unwrap());
+
+        // Pieces are verified against the root without the key.
+        let (chunk, proof) = file.get_chunk(2).unwrap();
+        let root = root_from_partial(&file.hasher(), &file.meta(), &chunk, 2, proof).unwrap();
+        assert_eq!(root, capability.root);
+        let (plain_chunk, _) = plain.get_chunk(2).unwrap();
+        assert_eq!(
+            capability.decrypt_chunk(2, &chunk.data).unwrap(),
+            plain_chunk.data
+        );
+        assert_eq!(capability.decrypt_file(&file).unwrap(), data);
+
+        let secret = ReadCapability {
+            key: EncryptionKey::generate().unwrap(),
+            ..capability
+        };
+        assert!(matches!(
+            secret.decrypt_file(&file),
+            Err(FileError::Decryption)
+        ));
+        assert!(matches!(secret.decrypt_file(&plain), Err(FileError::Proof)));
+    }
+}
@@ -0,0 +1,296 @@
+use bytes::Bytes;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    root_from_partial, AnyHash, Bitfield, Chunk, ChunkHasher, Chunking, File, FileError, FileMeta,
+    Piece,
+};
+
+/// Reed-Solomon parameters of an erasure coded file.
+///
+/// The chunks of a file are grouped in stripes of `data` chunks and every stripe gets `parity`
+/// coded chunks, so the stripe can be rebuilt from any `data` of its `data + parity` chunks. A
+/// stripe can't have more than 256 chunks.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{ErasureDecoder, ErasureParams, File};
+///
+/// let data: Vec<u8> = (0..10_000u32).map(|i| (i % 241) as u8).collect();
+/// let file = File::new(&data).unwrap();
+///
+/// let (coded, info) = ErasureParams::new(10, 4).encode(&file).unwrap();
+/// assert_eq!(coded.get_size(), 14);
+///
+/// // Any 10 of the 14 coded pieces are enough.
+/// let mut decoder = ErasureDecoder::new(info).unwrap();
+/// for idx in 4..14 {
+///     let (chunk, proof) = coded.get_chunk(idx).unwrap();
+///     decoder.add_chunk(idx, &chunk.data, proof).unwrap();
+/// }
+/// assert!(decoder.is_recoverable());
+/// assert_eq!(decoder.finish().unwrap(), data);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureParams {
+    pub data: usize,
+    pub parity: usize,
+}
+
+impl ErasureParams {
+    pub fn new(data: usize, parity: usize) -> Self {
+        Self { data, parity }
+    }
+
+    /// The number of chunks of a stripe.
+    pub fn stripe_len(&self) -> usize {
+        self.data + self.parity
+    }
+
+    /// Encodes the chunks of a file with fixed size chunking, the last chunk of the file and the
+    /// missing chunks of the last stripe are zero bytes. Returns the file of the coded chunks,
+    /// which has a merkle tree over all of them, and the info a decoder needs.
+    pub fn encode(&self, file: &File) -> Result<(File, ErasureInfo), FileError> {
+        let chunk_size = match file.chunking() {
+            Chunking::Fixed(chunk_size) => chunk_size,
+            Chunking::ContentDefined(_) => return Err(FileError::ChunkSize),
+        };
+        let codec = self.codec()?;
+
+        let mut coded = Vec::new();
+        for stripe in 0..file.get_size().div_ceil(self.data) {
+            let mut shards = (0..self.stripe_len())
+                .map(|i| {
+                    let idx = stripe * self.data + i;
+                    let mut shard = match i < self.data && idx < file.get_size() {
+                        true => file.storage().get(idx)?.data.to_vec(),
+                        false => vec![],
+                    };
+                    shard.resize(chunk_size, 0);
+                    Ok(shard)
+                })
+                .collect::<Result<Vec<Vec<u8>>, FileError>>()?;
+            codec.encode(&mut shards).map_err(|_| FileError::File)?;
+
+            for shard in shards {
+                coded.push(Chunk {
+                    data: shard.into(),
+                    leaf_idx: coded.len(),
+                });
+            }
+        }
+
+        let coded = File::from_chunks(coded, ChunkHasher::new(file.algorithm(), chunk_size))?;
+        let info = ErasureInfo {
+            root: file.get_root()?,
+            len: file.byte_len(),
+            chunk_size,
+            params: *self,
+            coded_root: coded.get_root()?,
+        };
+        Ok((coded, info))
+    }
+
+    fn codec(&self) -> Result<ReedSolomon, FileError> {
+        ReedSolomon::new(self.data, self.parity).map_err(|_| FileError::ChunkSize)
+    }
+}
+
+/// What a decoder needs to know about an erasure coded file, see [`ErasureParams::encode`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureInfo {
+    /// Root of the original file, the decoded file is checked against it.
+    pub root: AnyHash,
+    /// Length of the original file in bytes.
+    pub len: u64,
+    pub chunk_size: usize,
+    pub params: ErasureParams,
+    /// Root of the coded file, every piece is verified against it.
+    pub coded_root: AnyHash,
+}
+
+impl ErasureInfo {
+    pub fn stripe_count(&self) -> usize {
+        FileMeta::fixed(self.len, self.chunk_size)
+            .chunk_count
+            .div_ceil(self.params.data.max(1))
+    }
+
+    /// The meta of the coded file, all coded chunks have the full chunk size.
+    pub fn coded_meta(&self) -> FileMeta {
+        let chunk_count = self.stripe_count() * self.params.stripe_len();
+        FileMeta {
+            len: chunk_count as u64 * self.chunk_size as u64,
+            chunk_count,
+        }
+    }
+
+    pub fn coded_hasher(&self) -> ChunkHasher {
+        ChunkHasher::new(self.coded_root.algorithm(), self.chunk_size)
+    }
+}
+
+/// The receiving side of an erasure coded file, the counterpart of [`crate::PartialFile`].
+///
+/// Pieces of the coded file are verified against the coded root and kept in memory, the file
+/// is rebuilt as soon as every stripe has any `data` of its chunks.
+pub struct ErasureDecoder {
+    info: ErasureInfo,
+    chunks: Vec<Option<Bytes>>,
+    present: Bitfield,
+}
+
+impl ErasureDecoder {
+    /// Creates a decoder, returns `FileError::ChunkSize` if the parameters or the chunk size are
+    /// not valid.
+    pub fn new(info: ErasureInfo) -> Result<Self, FileError> {
+        info.params.codec()?;
+        if info.chunk_size == 0 {
+            return Err(FileError::ChunkSize);
+        }
+
+        let chunk_count = info.coded_meta().chunk_count;
+        Ok(Self {
+            info,
+            chunks: vec![None; chunk_count],
+            present: Bitfield::new(chunk_count),
+        })
+    }
+
+    pub fn info(&self) -> &ErasureInfo {
+        &self.info
+    }
+
+    /// Verifies a piece of the coded file, see [`crate::PartialFile::add_piece`].
+    pub fn add_piece(&mut self, idx: usize, piece: &Piece) -> Result<bool, FileError> {
+        let chunk = piece.decompress(self.info.chunk_size)?;
+        self.add_chunk(idx, &chunk.data, piece.proof.clone())
+    }
+
+    /// Same as [`ErasureDecoder::add_piece`], but takes the chunk data and proof directly.
+    pub fn add_chunk(
+        &mut self,
+        idx: usize,
+        data: &[u8],
+        proof: Vec<AnyHash>,
+    ) -> Result<bool, FileError> {
+        if idx >= self.chunks.len() {
+            return Err(FileError::File);
+        }
+        if data.len() != self.info.chunk_size {
+            return Err(FileError::ChunkSize);
+        }
+        if self.present.test(idx) {
+            return Ok(false);
+        }
+
+        let chunk = Chunk {
+            data: Bytes::copy_from_slice(data),
+            leaf_idx: idx,
+        };
+        let hasher = self.info.coded_hasher();
+        let root = root_from_partial(&hasher, &self.info.coded_meta(), &chunk, idx, proof)
+            .map_err(|_| FileError::Proof)?;
+        if root != self.info.coded_root {
+            return Err(FileError::Proof);
+        }
+
+        self.chunks[idx] = Some(chunk.data);
+        self.present.set(idx);
+        Ok(true)
+    }
+
+    /// Coded pieces that are present.
+    pub fn bitfield(&self) -> &Bitfield {
+        &self.present
+    }
+
+    /// Returns true if every stripe has enough chunks to be decoded.
+    pub fn is_recoverable(&self) -> bool {
+        self.chunks
+            .chunks(self.info.params.stripe_len())
+            .all(|stripe| stripe.iter().flatten().count() >= self.info.params.data)
+    }
+
+    /// Rebuilds the original file and checks it against the original root. Returns
+    /// `FileError::Incomplete` if a stripe doesn't have enough chunks yet.
+    pub fn finish(self) -> Result<Vec<u8>, FileError> {
+        if !self.is_recoverable() {
+            return Err(FileError::Incomplete);
+        }
+
+        let params = self.info.params;
+        let codec = params.codec()?;
+        let mut data = Vec::with_capacity(self.info.len as usize);
+        for stripe in self.chunks.chunks(params.stripe_len()) {
+            let mut shards: Vec<Option<Vec<u8>>> = stripe
+                .iter()
+                .map(|c| c.as_ref().map(|c| c.to_vec()))
+                .collect();
+            codec
+                .reconstruct_data(&mut shards)
+                .map_err(|_| FileError::Incomplete)?;
+
+            for shard in shards.into_iter().take(params.data).flatten() {
+                data.extend_from_slice(&shard);
+            }
+        }
+        data.truncate(self.info.len as usize);
+
+        let file = File::builder()
+            .hash(self.info.root.algorithm())
+            .chunk_size(self.info.chunk_size)
+            .build(&data)?;
+        if file.get_root()? != self.info.root {
+            return Err(FileError::Proof);
+        }
+        Ok(data)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_erasure_stripes() {
+        use super::*;
+        use crate::HashAlgorithm;
+
+        // 9 chunks in 3 stripes of 4 + 2, the last stripe has a single data chunk.
+        let data: Vec<u8> = (0..8500u32).map(|i| (i % 239) as u8).collect();
+        let file = File::new_with_hash(&data, HashAlgorithm::Sha384).unwrap();
+        let (coded, info) = ErasureParams::new(4, 2).encode(&file).unwrap();
+        assert_eq!(info.stripe_count(), 3);
+        assert_eq!(coded.get_size(), 18);
+        assert_eq!(coded.meta(), info.coded_meta());
+        assert_eq!(coded.get_root().unwrap(), info.coded_root);
+
+        // Drop two chunks of every stripe, data and parity alike.
+        let mut decoder = ErasureDecoder::new(info.clone()).unwrap();
+        for idx in (0..18).filter(|idx| ![0, 3, 7, 10, 16, 17].contains(idx)) {
+            let (chunk, proof) = coded.get_chunk(idx).unwrap();
+            assert!(!decoder.is_recoverable());
+            assert!(decoder.add_chunk(idx, &chunk.data, proof).unwrap());
+        }
+        assert!(decoder.is_recoverable());
+        assert_eq!(decoder.bitfield().count_ones(), 12);
+        assert_eq!(decoder.finish().unwrap(), data);
+
+        // Tampered and missing pieces.
+        let mut decoder = ErasureDecoder::new(info.clone()).unwrap();
+        let (chunk, proof) = coded.get_chunk(5).unwrap();
+        let mut tampered = chunk.data.to_vec();
+        tampered[0] ^= 1;
+        let res = decoder.add_chunk(5, &tampered, proof.clone());
+        assert!(matches!(res, Err(FileError::Proof)));
+        assert!(decoder.add_chunk(5, &chunk.data, proof).unwrap());
+        assert!(matches!(decoder.finish(), Err(FileError::Incomplete)));
+
+        let invalid = ErasureInfo {
+            params: ErasureParams::new(0, 0),
+            ..info
+        };
+        assert!(ErasureDecoder::new(invalid).is_err());
+        assert!(ErasureParams::new(200, 57).encode(&file).is_err());
+        assert!(ErasureParams::new(4, 0).encode(&file).is_err());
+    }
+}
@@ -1,18 +1,44 @@
-use crate::hasher::Sha256Hash;
+use std::path::Path;
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
 use crate::merkle::{self, MerkleError, MerkleTree};
-use crate::{AsBytes, Chunk, Hasher, Sha256Hasher};
-use lazy_static::lazy_static;
-use tokio::io::{AsyncRead, AsyncReadExt};
+use crate::{
+    AnyHash, AsBytes, Bitfield, BlockPiece, BlockTree, Chunk, ChunkStorage, Chunking, FileBuilder,
+    HashAlgorithm, Hasher, RangePiece, BLOCK_BYTES,
+};
+use tokio::io::AsyncRead;
 
-const CHUNK_BYTES: usize = 1024;
-lazy_static! {
-    static ref FILLER_HASH: Sha256Hash = Sha256Hash::new([0u8; 32]);
-}
+/// Default size of a chunk in bytes.
+pub const CHUNK_BYTES: usize = 1024;
+
+/// Domain and version of the tree format that are committed into every file root.
+const FILE_DOMAIN: &[u8] = b"pmtorrent-file-v2";
 
 #[derive(Debug)]
 pub enum FileError {
     Merkle(MerkleError),
     File,
+    ChunkSize,
+
+    /// The file on disk was modified after its merkle tree was built.
+    Changed,
+
+    /// A chunk doesn't match the trusted root.
+    Proof,
+
+    /// Some chunks of a file are still missing.
+    Incomplete,
+
+    /// Compressed chunk data can't be decoded.
+    Encoding,
+
+    /// An encrypted chunk can't be decrypted with the key.
+    Decryption,
+
+    /// Building the file was stopped with a [`crate::CancellationToken`].
+    Cancelled,
 }
 
 impl From<MerkleError> for FileError {
@@ -22,94 +48,523 @@ impl From<MerkleError> for FileError {
 }
 
 /// A structure to hold bytes of a file in chunks together with a custom merkle tree.
+///
+/// The chunks are either kept in memory, read from disk on demand for files created with
+/// [`File::open`] or sliced out of a memory mapping for files created with [`File::mmap`].
 pub struct File {
-    chunks: Vec<Chunk>,
+    storage: ChunkStorage,
     tree: ChunkMerkleTree,
 }
 
 impl File {
-    pub async fn from_reader<R>(mut reader: R) -> Result<Self, FileError>
+    /// Returns a builder to create a file with a non default hash algorithm or chunk size.
+    pub fn builder() -> FileBuilder {
+        FileBuilder::default()
+    }
+
+    pub async fn from_reader<R>(reader: R) -> Result<Self, FileError>
     where
         R: AsyncRead + Unpin,
     {
-        let mut buf = [0; CHUNK_BYTES];
-        let mut chunks = Vec::default();
-        let mut idx = 0;
-
-        loop {
-            let bytes = reader
-                .read(&mut buf[..])
-                .await
-                .map_err(|_| FileError::File)?;
-
-            if bytes == 0 {
-                break;
-            }
+        Self::builder().from_reader(reader).await
+    }
 
-            chunks.push(Chunk {
-                data: buf[..bytes].to_vec(),
-                leaf_idx: idx,
-            });
+    pub async fn from_reader_with_hash<R>(
+        reader: R,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, FileError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::builder().hash(algorithm).from_reader(reader).await
+    }
 
-            idx += 1;
-        }
+    pub fn new(data: &[u8]) -> Result<Self, FileError> {
+        Self::builder().build(data)
+    }
 
-        let tree = ChunkMerkleTree::new(&chunks)?;
-        Ok(Self { chunks, tree })
+    /// Builds the merkle tree of a file on disk without keeping its chunks in memory, see
+    /// [`crate::DiskFile`].
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+        Self::builder().open(path).await
     }
 
-    pub fn new(data: &[u8]) -> Result<Self, FileError> {
-        let chunks = Self::to_chunks(data);
-        let tree = ChunkMerkleTree::new(&chunks)?;
+    /// Builds the merkle tree of a memory mapped file, see [`crate::MmapFile`].
+    pub fn mmap<P: AsRef<Path>>(path: P) -> Result<Self, FileError> {
+        Self::builder().mmap(path)
+    }
+
+    pub fn new_with_hash(data: &[u8], algorithm: HashAlgorithm) -> Result<Self, FileError> {
+        Self::builder().hash(algorithm).build(data)
+    }
+
+    pub(crate) fn from_chunks(chunks: Vec<Chunk>, hasher: ChunkHasher) -> Result<Self, FileError> {
+        let tree = ChunkMerkleTree::new(hasher, &chunks)?;
+        let storage = ChunkStorage::Memory(chunks);
+
+        Ok(Self { storage, tree })
+    }
+
+    pub(crate) fn from_storage(storage: ChunkStorage, tree: ChunkMerkleTree) -> Self {
+        Self { storage, tree }
+    }
 
-        Ok(Self { chunks, tree })
+    pub(crate) fn storage(&self) -> &ChunkStorage {
+        &self.storage
     }
 
-    pub fn get_root(&self) -> Result<Sha256Hash, FileError> {
+    pub(crate) fn set_storage(&mut self, storage: ChunkStorage) {
+        self.storage = storage;
+    }
+
+    /// Builds a copy of the file with the merkle tree hashed by another algorithm.
+    pub fn rehash(&self, algorithm: HashAlgorithm) -> Result<Self, FileError> {
+        let hasher = ChunkHasher {
+            algorithm,
+            ..self.hasher()
+        };
+        let leaf_hashes = (0..self.get_size())
+            .map(|idx| Ok(hasher.leaf_hash(&self.storage.get(idx)?)))
+            .collect::<Result<Vec<AnyHash>, FileError>>()?;
+        let tree = ChunkMerkleTree::from_leaf_hashes(hasher, leaf_hashes, self.byte_len())?;
+
+        Ok(Self::from_storage(self.storage.clone(), tree))
+    }
+
+    /// The root of the file, it commits to the merkle tree of the chunks as well as to the
+    /// length of the file and its chunking, see [`ChunkHasher::file_root`].
+    pub fn get_root(&self) -> Result<AnyHash, FileError> {
         self.tree.root()
     }
 
+    /// A visual fingerprint (randomart) of the root hash, see [`crate::randomart`]. Compare the
+    /// root itself to tell whether two files are the same.
+    pub fn fingerprint(&self) -> String {
+        self.get_root()
+            .map(|root| root.randomart())
+            .unwrap_or_default()
+    }
+
+    /// The hash algorithm that was used to build the merkle tree of the file.
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.tree.hasher.algorithm
+    }
+
+    /// Size of the chunks in bytes, only the last chunk of the file can be shorter. For
+    /// content-defined chunking it's the maximum chunk size.
+    pub fn chunk_size(&self) -> usize {
+        self.tree.hasher.chunk_size()
+    }
+
+    pub fn chunking(&self) -> Chunking {
+        self.tree.hasher.chunking
+    }
+
+    /// Lengths of all chunks for files with content-defined chunking, the receiving side needs
+    /// them to know where every chunk starts. Fixed size chunks have no lengths recorded.
+    ///
+    /// Fails if a chunk of a file in a [`crate::FileRepo`] can't be read from its chunk store.
+    pub fn chunk_lengths(&self) -> Result<Option<Vec<usize>>, FileError> {
+        match self.chunking() {
+            Chunking::Fixed(_) => Ok(None),
+            Chunking::ContentDefined(_) => self.storage.chunk_lengths().map(Some),
+        }
+    }
+
+    /// The leaf hasher that is needed to verify chunks with [`root_from_partial`].
+    pub fn hasher(&self) -> ChunkHasher {
+        self.tree.hasher
+    }
+
+    /// The length and the number of chunks that are needed to verify chunks with
+    /// [`root_from_partial`].
+    pub fn meta(&self) -> FileMeta {
+        self.tree.meta
+    }
+
+    /// Length of the file in bytes.
+    pub fn byte_len(&self) -> u64 {
+        self.tree.meta.len
+    }
+
     pub fn get_size(&self) -> usize {
-        self.chunks.len()
+        self.storage.len()
     }
 
-    pub fn get_chunk(&self, idx: usize) -> Result<(Chunk, Vec<crate::Sha256Hash>), FileError> {
-        let chunk = self.chunks.get(idx).cloned().ok_or(FileError::File)?;
+    /// A bitfield with all chunks of the file set.
+    pub fn bitfield(&self) -> Bitfield {
+        Bitfield::full(self.get_size())
+    }
+
+    pub fn get_chunk(&self, idx: usize) -> Result<(Chunk, Vec<AnyHash>), FileError> {
+        let chunk = self.storage.get(idx)?;
         let proof = self.tree.get_proof_hashes(chunk.leaf_idx)?;
 
         Ok((chunk, proof))
     }
 
-    pub fn trusted_root(&self) -> Result<Sha256Hash, FileError> {
-        Ok(self.tree.tree.last().ok_or(FileError::File)?.to_owned())
+    /// Offset of the first byte of the chunk at the provided idx.
+    pub fn chunk_offset(&self, idx: usize) -> Result<u64, FileError> {
+        if idx >= self.get_size() {
+            return Err(FileError::Merkle(MerkleError::InvalidIdx));
+        }
+
+        match self.chunking() {
+            Chunking::Fixed(chunk_size) => Ok(idx as u64 * chunk_size as u64),
+            Chunking::ContentDefined(_) => Ok(self.chunk_starts()?[idx]),
+        }
+    }
+
+    /// Reads `len` bytes at `offset` together with the chunks that cover them and a single range
+    /// proof, see [`RangePiece`]. The span must not be empty or go past the end of the file.
+    pub fn read_at(&self, offset: u64, len: usize) -> Result<RangePiece, FileError> {
+        let end = offset
+            .checked_add(len as u64)
+            .filter(|&end| len > 0 && end <= self.byte_len())
+            .ok_or(FileError::Merkle(MerkleError::InvalidIdx))?;
+
+        let (first, last) = match self.chunking() {
+            Chunking::Fixed(chunk_size) => {
+                let chunk_size = chunk_size as u64;
+                (
+                    (offset / chunk_size) as usize,
+                    ((end - 1) / chunk_size) as usize,
+                )
+            }
+            Chunking::ContentDefined(_) => {
+                let starts = self.chunk_starts()?;
+                let first = starts.partition_point(|&start| start <= offset) - 1;
+                (first, starts.partition_point(|&start| start < end) - 1)
+            }
+        };
+        let chunks = (first..=last)
+            .map(|idx| self.storage.get(idx))
+            .collect::<Result<Vec<Chunk>, FileError>>()?;
+
+        Ok(RangePiece {
+            offset,
+            len,
+            first_chunk: first,
+            chunks,
+            proof: self.tree.get_range_proof_hashes(first, last)?,
+        })
+    }
+
+    /// Offsets of all content-defined chunks in the file.
+    fn chunk_starts(&self) -> Result<Vec<u64>, FileError> {
+        Ok(self
+            .storage
+            .chunk_lengths()?
+            .iter()
+            .scan(0, |offset, &len| {
+                let start = *offset;
+                *offset += len as u64;
+                Some(start)
+            })
+            .collect())
+    }
+
+    /// Returns the block at `block_idx` of the chunk at `chunk_idx` with the proofs to verify it,
+    /// see [`BlockPiece`]. Blocks that only cover the padding of the last chunk are empty.
+    pub fn get_block(&self, chunk_idx: usize, block_idx: usize) -> Result<BlockPiece, FileError> {
+        let (chunk, leaf_proof) = self.get_chunk(chunk_idx)?;
+        let tree = BlockTree::new(&self.hasher(), &chunk)?;
+        let block_proof = tree.block_proof(block_idx)?;
+
+        let start = (block_idx * BLOCK_BYTES).min(chunk.len());
+        let end = (start + BLOCK_BYTES).min(chunk.len());
+        Ok(BlockPiece {
+            content: Chunk {
+                data: chunk.data.slice(start..end),
+                leaf_idx: block_idx,
+            },
+            block_proof,
+            leaf_hash: tree.root(),
+            leaf_proof,
+        })
     }
 
-    fn to_chunks(data: &[u8]) -> Vec<Chunk> {
+    /// Indices of the chunks that differ between two versions of a file, including the chunks
+    /// that only one of them has. The leaf hashes of the trees are compared, no chunk is read.
+    /// Returns `FileError::ChunkSize` if the files don't share the hash algorithm and chunking.
+    pub fn changed_leaves(&self, other: &File) -> Result<Vec<usize>, FileError> {
+        let hasher = self.hasher();
+        if hasher != other.hasher() {
+            return Err(FileError::ChunkSize);
+        }
+
+        let (old, new) = (self.meta(), other.meta());
+        let (old_hashes, new_hashes) = (self.tree.leaf_hashes(), other.tree.leaf_hashes());
+        let changed = (0..old.chunk_count.max(new.chunk_count))
+            .filter(|&idx| {
+                // A shorter last chunk has the leaf hash of its zero padded data.
+                old_hashes.get(idx) != new_hashes.get(idx)
+                    || old.chunk_len(&hasher, idx).ok() != new.chunk_len(&hasher, idx).ok()
+            })
+            .collect();
+        Ok(changed)
+    }
+
+    pub(crate) fn tree(&self) -> &ChunkMerkleTree {
+        &self.tree
+    }
+
+    pub fn trusted_root(&self) -> Result<AnyHash, FileError> {
+        self.get_root()
+    }
+
+    /// Splits the buffer in chunks that share the same allocation.
+    pub(crate) fn to_chunks(data: &Bytes, chunking: &Chunking) -> Vec<Chunk> {
         let mut chunks = vec![];
-        for (i, c) in data.chunks(CHUNK_BYTES).enumerate() {
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = chunking.next_cut(&data[offset..]);
             chunks.push(Chunk {
-                data: c.to_owned(),
-                leaf_idx: i,
+                data: data.slice(offset..offset + len),
+                leaf_idx: chunks.len(),
             });
+            offset += len;
         }
 
         chunks
     }
 }
 
+/// A hasher for the leaves of [`ChunkMerkleTree`].
+///
+/// With fixed size chunking the chunks that are shorter than the chunk size are padded with zero
+/// bytes before they are hashed, content-defined chunks are hashed as they are. A chunk that is
+/// longer than [`BLOCK_BYTES`] is hashed as the root of its [`BlockTree`]. The inner nodes are
+/// hashed with the plain `algorithm`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChunkHasher {
+    pub algorithm: HashAlgorithm,
+    pub chunking: Chunking,
+}
+
+impl ChunkHasher {
+    /// A hasher for fixed size chunks of `chunk_size` bytes.
+    pub fn new(algorithm: HashAlgorithm, chunk_size: usize) -> Self {
+        Self {
+            algorithm,
+            chunking: Chunking::Fixed(chunk_size),
+        }
+    }
+
+    /// The size of fixed size chunks or the maximum size of content-defined chunks.
+    pub fn chunk_size(&self) -> usize {
+        self.chunking.max_chunk_size()
+    }
+
+    /// Hash of a leaf node, i.e. the root of the [`BlockTree`] of the chunk padded to the chunk
+    /// size if the chunking is padded. A chunk that fits in a single block is hashed directly.
+    pub fn leaf_hash(&self, chunk: &Chunk) -> AnyHash {
+        if self.block_count(chunk.len()) == 1 {
+            return self.digest(&self.padded(chunk));
+        }
+        BlockTree::new(self, chunk)
+            .expect("block tree of a chunk")
+            .root()
+    }
+
+    /// The length of a chunk of `chunk_len` bytes after padding.
+    pub fn padded_len(&self, chunk_len: usize) -> usize {
+        if self.chunking.is_padded() {
+            chunk_len.max(self.chunk_size())
+        } else {
+            chunk_len
+        }
+    }
+
+    /// The number of blocks of a chunk of `chunk_len` bytes, every chunk has at least one.
+    pub fn block_count(&self, chunk_len: usize) -> usize {
+        self.padded_len(chunk_len).div_ceil(BLOCK_BYTES).max(1)
+    }
+
+    /// The data of the chunk padded to the chunk size if the chunking is padded.
+    pub(crate) fn padded(&self, chunk: &Chunk) -> Bytes {
+        let padded_len = self.padded_len(chunk.len());
+        if padded_len == chunk.len() {
+            return chunk.data.clone();
+        }
+
+        let mut p = vec![0u8; padded_len];
+        p[..chunk.len()].copy_from_slice(chunk.as_bytes());
+        p.into()
+    }
+
+    /// The root of a file: the hash of the tree format, the hash algorithm, the chunking, the
+    /// file length, the number of chunks and the root of the merkle tree of the chunks.
+    ///
+    /// The padding of the last chunk and the filler leaves make the tree root alone ambiguous,
+    /// e.g. `[1, 0]` and `[1]` have the same tree, but not the same file root.
+    pub fn file_root(&self, meta: &FileMeta, tree_root: &AnyHash) -> AnyHash {
+        let message = [
+            FILE_DOMAIN,
+            self.algorithm.name().as_bytes(),
+            &[0],
+            &self.chunking.encode(),
+            &meta.len.to_be_bytes(),
+            &(meta.chunk_count as u64).to_be_bytes(),
+            tree_root.as_bytes(),
+        ]
+        .concat();
+
+        self.digest(&message)
+    }
+}
+
+/// The length of a file in bytes and the number of its chunks, both are committed into the
+/// file root.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileMeta {
+    pub len: u64,
+    pub chunk_count: usize,
+}
+
+impl FileMeta {
+    /// Meta of a file of `len` bytes that is split in fixed size chunks.
+    pub fn fixed(len: u64, chunk_size: usize) -> Self {
+        Self {
+            len,
+            chunk_count: len.div_ceil(chunk_size.max(1) as u64) as usize,
+        }
+    }
+
+    /// The range of valid lengths of the chunk at the provided idx, fixed size chunks have a
+    /// single valid length.
+    pub fn chunk_len(
+        &self,
+        hasher: &ChunkHasher,
+        idx: usize,
+    ) -> Result<std::ops::RangeInclusive<usize>, FileError> {
+        if idx >= self.chunk_count {
+            return Err(FileError::Merkle(MerkleError::InvalidIdx));
+        }
+
+        match hasher.chunking {
+            Chunking::Fixed(chunk_size) => {
+                if *self != Self::fixed(self.len, chunk_size) {
+                    return Err(FileError::ChunkSize);
+                }
+                let offset = idx as u64 * chunk_size as u64;
+                let len = (self.len - offset).min(chunk_size as u64) as usize;
+                Ok(len..=len)
+            }
+            Chunking::ContentDefined(params) => Ok(1..=params.max),
+        }
+    }
+}
+
+impl Hasher for ChunkHasher {
+    type Hash = AnyHash;
+
+    fn digest(&self, data: &[u8]) -> AnyHash {
+        self.algorithm.hasher().digest(data)
+    }
+}
+
+#[derive(Clone)]
 pub struct ChunkMerkleTree {
-    tree: Vec<Sha256Hash>,
+    tree: Vec<AnyHash>,
+    hasher: ChunkHasher,
+    meta: FileMeta,
 }
 
 impl ChunkMerkleTree {
-    pub fn new(chunks: &[Chunk]) -> Result<Self, FileError> {
-        let hasher = Sha256Hasher {};
+    pub fn new(hasher: ChunkHasher, chunks: &[Chunk]) -> Result<Self, FileError> {
         let tree = Self::build_tree(&hasher, chunks)?;
+        let meta = FileMeta {
+            len: chunks.iter().map(|c| c.len() as u64).sum(),
+            chunk_count: chunks.len(),
+        };
+
+        Ok(Self { tree, hasher, meta })
+    }
+
+    /// Builds a tree from the leaf hashes that were calculated with [`ChunkHasher::leaf_hash`]
+    /// for a file of `len` bytes.
+    pub fn from_leaf_hashes(
+        hasher: ChunkHasher,
+        mut leaf_hashes: Vec<AnyHash>,
+        len: u64,
+    ) -> Result<Self, FileError> {
+        let meta = FileMeta {
+            len,
+            chunk_count: leaf_hashes.len(),
+        };
+        pad_leaf_hashes(&hasher, &mut leaf_hashes);
+        let tree = Self::build_tree_from_first_level(&hasher, leaf_hashes)?;
+
+        Ok(Self { tree, hasher, meta })
+    }
+
+    /// Builds the tree of another version of the file of `len` bytes from its leaf hashes. If
+    /// the padded number of leaves doesn't change, the unchanged subtrees are copied and only the
+    /// nodes above the changed leaves are hashed.
+    pub fn with_leaf_hashes(
+        &self,
+        mut leaf_hashes: Vec<AnyHash>,
+        len: u64,
+    ) -> Result<Self, FileError> {
+        let meta = FileMeta {
+            len,
+            chunk_count: leaf_hashes.len(),
+        };
+        pad_leaf_hashes(&self.hasher, &mut leaf_hashes);
+        let leaf_count = self.get_leaf_count();
+        if leaf_hashes.len() != leaf_count {
+            let tree = Self::build_tree_from_first_level(&self.hasher, leaf_hashes)?;
+            return Ok(Self {
+                tree,
+                hasher: self.hasher,
+                meta,
+            });
+        }
+
+        let mut tree = self.tree.clone();
+        let mut dirty: Vec<usize> = (0..leaf_count)
+            .filter(|&idx| tree[idx] != leaf_hashes[idx])
+            .collect();
+        tree[..leaf_count].clone_from_slice(&leaf_hashes);
+
+        // Walk up the levels of the reversed heap and rehash the parents of dirty nodes.
+        let (mut offset, mut width) = (0, leaf_count);
+        while width > 1 {
+            let parent_offset = offset + width;
+            let mut parents: Vec<usize> = dirty.iter().map(|idx| idx / 2).collect();
+            parents.dedup();
+            for &parent in &parents {
+                let l = &tree[offset + 2 * parent];
+                let r = &tree[offset + 2 * parent + 1];
+                tree[parent_offset + parent] =
+                    self.hasher.digest(&[l.as_bytes(), r.as_bytes()].concat());
+            }
+
+            dirty = parents;
+            offset = parent_offset;
+            width /= 2;
+        }
+
+        Ok(Self {
+            tree,
+            hasher: self.hasher,
+            meta,
+        })
+    }
 
-        Ok(Self { tree })
+    /// Leaf hashes of the chunks without the filler hashes.
+    pub fn leaf_hashes(&self) -> &[AnyHash] {
+        &self.tree[..self.meta.chunk_count]
     }
 
-    pub fn root(&self) -> Result<Sha256Hash, FileError> {
+    /// The root of the file, see [`ChunkHasher::file_root`].
+    pub fn root(&self) -> Result<AnyHash, FileError> {
+        Ok(self.hasher.file_root(&self.meta, &self.tree_root()?))
+    }
+
+    /// The root of the merkle tree of the chunks.
+    pub fn tree_root(&self) -> Result<AnyHash, FileError> {
         Ok(self
             .tree
             .last()
@@ -118,82 +573,59 @@ impl ChunkMerkleTree {
     }
 }
 
-impl MerkleTree<Chunk, Sha256Hasher> for ChunkMerkleTree {
-    fn get_tree(&self) -> &[Sha256Hash] {
+impl MerkleTree<Chunk, ChunkHasher> for ChunkMerkleTree {
+    fn get_tree(&self) -> &[AnyHash] {
         &self.tree
     }
 
     /// Custom implementation for [`MerkleTree::build_first_level`] method.
-    /// It pads the last leaf if it doesn't have the exact size of `CHUNK_BYTES` and appends
-    /// `FILLER_HASH` to the leaf vector if it's size is not in power of 2.
+    /// It pads the last leaf if it doesn't have the exact size of `ChunkHasher::chunk_size` (for
+    /// fixed size chunking) and
+    /// appends a filler hash (all zero bytes) to the leaf vector if it's size is not in power of 2.
     fn build_first_level(
-        hasher: &Sha256Hasher,
+        hasher: &ChunkHasher,
         leaves: &[Chunk],
-    ) -> Result<Vec<<Sha256Hasher as Hasher>::Hash>, MerkleError> {
+    ) -> Result<Vec<<ChunkHasher as Hasher>::Hash>, MerkleError> {
         let mut padded_hashes = leaves
             .iter()
-            .map(|l| pad_payload(hasher, l))
-            .collect::<Vec<Sha256Hash>>();
-
-        let next_pow2 = next_pow2(leaves.len());
-        if next_pow2 != leaves.len() {
-            padded_hashes.resize(next_pow2, FILLER_HASH.clone());
-        }
+            .map(|l| hasher.leaf_hash(l))
+            .collect::<Vec<AnyHash>>();
 
+        pad_leaf_hashes(hasher, &mut padded_hashes);
         Ok(padded_hashes)
     }
 }
 
-#[allow(dead_code)]
-pub fn root_from_partial(
-    hasher: &Sha256Hasher,
-    leaf: &Chunk,
-    leaf_idx: usize,
-    leaf_count: usize,
-    hashes: Vec<Sha256Hash>,
-) -> Result<Sha256Hash, FileError> {
-    let padded_leaf = if leaf.data.len() < CHUNK_BYTES {
-        pad_data(leaf)
-    } else {
-        leaf.to_owned()
-    };
-
-    merkle::root_from_partial(hasher, &padded_leaf, leaf_idx, leaf_count, hashes)
-        .map_err(FileError::Merkle)
-}
-
-fn pad_data(c: &Chunk) -> Chunk {
-    let mut p = [0u8; CHUNK_BYTES];
-    for (i, b) in c.as_bytes().iter().enumerate() {
-        p[i] = *b;
-    }
-    Chunk {
-        data: p.to_vec(),
-        leaf_idx: c.leaf_idx,
+/// An empty file has no chunks, its tree has a single filler leaf.
+fn pad_leaf_hashes(hasher: &ChunkHasher, leaf_hashes: &mut Vec<AnyHash>) {
+    let leaf_count = leaf_hashes.len().next_power_of_two();
+    if leaf_count != leaf_hashes.len() {
+        leaf_hashes.resize(leaf_count, hasher.algorithm.zero_hash());
     }
 }
 
-fn pad_payload(hasher: &Sha256Hasher, l: &Chunk) -> Sha256Hash {
-    if l.len() < CHUNK_BYTES {
-        let mut p = [0u8; CHUNK_BYTES];
-        for (i, b) in l.as_bytes().iter().enumerate() {
-            p[i] = *b;
-        }
-        return hasher.digest(&p);
+/// Calculates the file root from a chunk at `leaf_idx` and its proof hashes, see
+/// [`ChunkHasher::file_root`].
+///
+/// The length of the chunk is checked against `meta` first: fixed size chunks must have their
+/// exact length and content-defined chunks can't be empty or longer than the maximum size, so a
+/// chunk with stripped or extra zero bytes is rejected with `FileError::ChunkSize`.
+pub fn root_from_partial(
+    hasher: &ChunkHasher,
+    meta: &FileMeta,
+    leaf: &Chunk,
+    leaf_idx: usize,
+    hashes: Vec<AnyHash>,
+) -> Result<AnyHash, FileError> {
+    if !meta.chunk_len(hasher, leaf_idx)?.contains(&leaf.len()) {
+        return Err(FileError::ChunkSize);
     }
-    hasher.digest(l.as_bytes())
-}
 
-fn next_pow2(n: usize) -> usize {
-    let mut n = n - 1;
-    let mut i = 0;
+    let leaf_count = meta.chunk_count.next_power_of_two();
+    let tree_root =
+        merkle::root_from_leaf_hash(hasher, hasher.leaf_hash(leaf), leaf_idx, leaf_count, hashes)?;
 
-    while i <= 4 {
-        n |= n >> 2u8.pow(i);
-        i += 1;
-    }
-
-    n + 1
+    Ok(hasher.file_root(meta, &tree_root))
 }
 
 mod tests {
@@ -202,13 +634,13 @@ mod tests {
         use super::*;
 
         let data = [1u8; 6144]; // exactly 6 full chunks.
-        let chunks = File::to_chunks(&data);
+        let chunks = File::to_chunks(&Bytes::copy_from_slice(&data), &Chunking::default());
 
         assert_eq!(chunks.len(), 6);
         assert_eq!(chunks.get(5).unwrap().data.get(42).unwrap(), &1);
 
         let data = [1u8; 6145]; // 7 chunks, the last one has only one byte.
-        let chunks = File::to_chunks(&data);
+        let chunks = File::to_chunks(&Bytes::copy_from_slice(&data), &Chunking::default());
 
         assert_eq!(chunks.len(), 7);
         assert_eq!(chunks.get(6).unwrap().data.first().unwrap(), &1);
@@ -219,14 +651,15 @@ mod tests {
     fn test_build_first_level() {
         use super::*;
 
-        let chunks = File::to_chunks(&[1u8; 6144]);
-        let chunk_tree = ChunkMerkleTree::new(&chunks);
+        let chunks = File::to_chunks(&Bytes::from(vec![1u8; 6144]), &Chunking::default());
+        let chunk_tree = ChunkMerkleTree::new(ChunkHasher::default(), &chunks);
         assert!(chunk_tree.is_ok());
 
         let chunk_tree = chunk_tree.unwrap();
+        let filler_hash = HashAlgorithm::Sha256.zero_hash();
         assert_eq!(chunk_tree.tree.len(), 15);
-        assert_eq!(chunk_tree.tree[6], FILLER_HASH.clone());
-        assert_eq!(chunk_tree.tree[7], FILLER_HASH.clone());
+        assert_eq!(chunk_tree.tree[6], filler_hash);
+        assert_eq!(chunk_tree.tree[7], filler_hash);
     }
 
     #[test]
@@ -235,27 +668,173 @@ mod tests {
 
         let data = [0u8; 6145];
         let file = File::new(&data).unwrap();
-        assert_eq!(file.chunks.len(), 7);
+        assert_eq!(file.get_size(), 7);
 
         let (chunk, proof) = file.get_chunk(6).unwrap();
         assert_eq!(chunk.data.first().unwrap(), &0);
         assert_eq!(chunk.data.get(1), None);
         assert_eq!(proof.len(), 3);
 
-        let hasher = Sha256Hasher;
+        let hasher = file.hasher();
         let trusted_root = file.trusted_root().unwrap();
         let untrusted_root =
-            super::root_from_partial(&hasher, &chunk, chunk.leaf_idx, 8, proof).unwrap();
+            super::root_from_partial(&hasher, &file.meta(), &chunk, chunk.leaf_idx, proof).unwrap();
         assert_eq!(untrusted_root, trusted_root);
     }
 
     #[test]
-    fn test_next_pow2() {
+    fn test_new_file_with_hash() {
+        use super::*;
+
+        let data = [7u8; 3000];
+        let sha256 = File::new(&data).unwrap();
+        let sha512 = File::new_with_hash(&data, HashAlgorithm::Sha512).unwrap();
+        assert_eq!(sha256.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(sha512.algorithm(), HashAlgorithm::Sha512);
+        assert_ne!(sha256.get_root().unwrap(), sha512.get_root().unwrap());
+
+        let (chunk, proof) = sha512.get_chunk(1).unwrap();
+        assert!(proof.iter().all(|h| h.algorithm() == HashAlgorithm::Sha512));
+
+        let hasher = sha512.hasher();
+        let untrusted_root =
+            super::root_from_partial(&hasher, &sha512.meta(), &chunk, 1, proof).unwrap();
+        assert_eq!(untrusted_root, sha512.trusted_root().unwrap());
+    }
+
+    #[test]
+    fn test_custom_chunk_size() {
         use super::*;
 
-        assert_eq!(next_pow2(4), 4);
-        assert_eq!(next_pow2(6), 8);
-        assert_eq!(next_pow2(9), 16);
+        let data = [9u8; 10_000];
+        let file = File::builder().chunk_size(4096).build(&data).unwrap();
+        assert_eq!(file.chunk_size(), 4096);
+        assert_eq!(file.get_size(), 3);
+        assert_ne!(
+            file.get_root().unwrap(),
+            File::new(&data).unwrap().get_root().unwrap()
+        );
+
+        let (chunk, proof) = file.get_chunk(2).unwrap();
+        assert_eq!(chunk.len(), 10_000 - 2 * 4096);
+        assert_eq!(proof.len(), 2);
+
+        let untrusted_root =
+            super::root_from_partial(&file.hasher(), &file.meta(), &chunk, 2, proof.clone());
+        assert_eq!(untrusted_root.unwrap(), file.trusted_root().unwrap());
+
+        // The chunk size is committed into the root, so a wrong chunk size yields a wrong root.
+        let hasher = ChunkHasher::new(file.algorithm(), 2048);
+        let meta = FileMeta::fixed(6144 + chunk.len() as u64, 2048);
+        let untrusted_root = super::root_from_partial(&hasher, &meta, &chunk, 3, proof).unwrap();
+        assert_ne!(untrusted_root, file.trusted_root().unwrap());
+    }
+
+    #[test]
+    fn test_root_commits_to_length() {
+        use super::*;
+
+        // Both files have the same merkle tree, the last chunk is padded with zero bytes.
+        let short = File::new(&[1]).unwrap();
+        let long = File::new(&[1, 0]).unwrap();
+        assert_eq!(
+            short.tree.tree_root().unwrap(),
+            long.tree.tree_root().unwrap()
+        );
+        assert_ne!(short.get_root().unwrap(), long.get_root().unwrap());
+
+        // A chunk with stripped zero bytes is rejected, even with the right proof.
+        let (chunk, proof) = long.get_chunk(0).unwrap();
+        let stripped = Chunk {
+            data: chunk.data.slice(..1),
+            leaf_idx: 0,
+        };
+        let res = super::root_from_partial(&long.hasher(), &long.meta(), &stripped, 0, proof);
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+
+        // The meta must be consistent with the chunking.
+        let meta = FileMeta {
+            len: 2,
+            chunk_count: 2,
+        };
+        let res = super::root_from_partial(&long.hasher(), &meta, &chunk, 0, vec![]);
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+
+        let empty = File::new(&[]).unwrap();
+        assert_eq!(empty.get_size(), 0);
+        assert_ne!(
+            empty.get_root().unwrap(),
+            File::builder()
+                .chunk_size(2048)
+                .build(&[])
+                .unwrap()
+                .get_root()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        use super::*;
+        use crate::{test_data, verify_block, CdcParams};
+
+        let data = test_data(150_000);
+        let file = File::builder().chunk_size(64 * 1024).build(&data).unwrap();
+        let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+
+        // Every block of every chunk is verified on its own, the blocks together are the chunk.
+        for chunk_idx in 0..file.get_size() {
+            let (chunk, _) = file.get_chunk(chunk_idx).unwrap();
+            let mut blocks = vec![];
+            for block_idx in 0..hasher.block_count(chunk.len()) {
+                let block = file.get_block(chunk_idx, block_idx).unwrap();
+                assert_eq!(block.leaf_hash, hasher.leaf_hash(&chunk));
+                assert!(block
+                    .verify(&hasher, &meta, &root, chunk_idx, block_idx, chunk.len())
+                    .is_ok());
+                blocks.extend_from_slice(&block.content.data);
+            }
+            assert_eq!(blocks, chunk.data);
+        }
+        assert!(file.get_block(0, 4).is_err());
+
+        // A tampered block is rejected against the leaf hash.
+        let mut block = file.get_block(1, 2).unwrap();
+        let mut tampered = block.content.data.to_vec();
+        tampered[100] ^= 1;
+        block.content.data = tampered.into();
+        let res = block.verify(&hasher, &meta, &root, 1, 2, 64 * 1024);
+        assert!(matches!(res, Err(FileError::Proof)));
+
+        // Content-defined chunks are split in blocks without padding.
+        let params = CdcParams::new(20_000, 40_000, 80_000);
+        let file = File::builder()
+            .content_defined(params)
+            .build(&data)
+            .unwrap();
+        let lengths = file.chunk_lengths().unwrap().unwrap();
+        let last = file.hasher().block_count(lengths[0]) - 1;
+        let block = file.get_block(0, last).unwrap();
+        let res = verify_block(
+            &file.hasher(),
+            &block.leaf_hash,
+            lengths[0],
+            last,
+            &block.content,
+            block.block_proof.clone(),
+        );
+        assert!(res.is_ok());
+
+        // The length of the chunk has to be known to check the length of its last block.
+        let res = block.verify(
+            &file.hasher(),
+            &file.meta(),
+            &file.get_root().unwrap(),
+            0,
+            last,
+            lengths[0] - 1,
+        );
+        assert!(matches!(res, Err(FileError::ChunkSize)));
     }
 
     #[test]
@@ -275,7 +854,7 @@ mod tests {
             if prev_char != c {
                 if counter > 0 {
                     res.push_str(&counter.to_string());
-                    res.push_str(&prev_char.to_string());
+                    res.push(prev_char);
                 }
                 counter = 0;
             }
@@ -286,7 +865,7 @@ mod tests {
 
         if counter > 0 {
             res.push_str(&counter.to_string());
-            res.push_str(&prev_char.to_string());
+            res.push(prev_char);
         }
 
         res
@@ -0,0 +1,180 @@
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{AnyHash, Chunk, ChunkHasher, ChunkMerkleTree, ChunkStorage, File, FileError};
+
+/// Bytes requested from the source by a single read of [`GrowingFile::read_from`].
+const READ_BYTES: usize = 64 * 1024;
+
+/// A file that is still being written, e.g. a log file or a recording.
+///
+/// Appended bytes are cut in chunks as soon as the cut can't move anymore: a fixed size chunk
+/// once it's full and a content-defined chunk once the longest possible chunk is buffered. Final
+/// chunks are hashed once, only the bytes after them are hashed again for every snapshot.
+///
+/// Every [`GrowingFile::snapshot`] is a regular [`File`] of the bytes appended so far, its root
+/// commits to that size, so a client verifies the prefix it downloads against the root of a
+/// snapshot and fetches the appended pieces of the next one. The tree of a snapshot is built
+/// from the tree of the previous one, see [`ChunkMerkleTree::with_leaf_hashes`].
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::File;
+///
+/// let mut growing = File::builder().chunk_size(1024).growing().unwrap();
+/// growing.append(&[1u8; 1500]);
+/// let first = growing.snapshot().unwrap();
+/// assert_eq!((first.byte_len(), first.get_size()), (1500, 2));
+///
+/// growing.append(&[2u8; 1000]);
+/// let second = growing.snapshot().unwrap();
+/// assert_eq!(second.byte_len(), 2500);
+/// assert_eq!(first.changed_leaves(&second).unwrap(), vec![1, 2]);
+///
+/// let mut data = vec![1u8; 1500];
+/// data.extend_from_slice(&[2u8; 1000]);
+/// let built = File::builder().chunk_size(1024).build(&data).unwrap();
+/// assert_eq!(second.get_root().unwrap(), built.get_root().unwrap());
+/// ```
+pub struct GrowingFile {
+    hasher: ChunkHasher,
+    chunks: Vec<Chunk>,
+    leaf_hashes: Vec<AnyHash>,
+    /// Appended bytes that aren't cut in final chunks yet.
+    tail: Vec<u8>,
+    /// The tree of the last snapshot.
+    tree: Option<ChunkMerkleTree>,
+}
+
+impl GrowingFile {
+    pub(crate) fn new(hasher: ChunkHasher) -> Self {
+        Self {
+            hasher,
+            chunks: vec![],
+            leaf_hashes: vec![],
+            tail: vec![],
+            tree: None,
+        }
+    }
+
+    /// Length of the appended bytes.
+    pub fn byte_len(&self) -> u64 {
+        self.chunks.iter().map(|c| c.len() as u64).sum::<u64>() + self.tail.len() as u64
+    }
+
+    pub fn append(&mut self, data: &[u8]) {
+        self.tail.extend_from_slice(data);
+
+        let max_chunk_size = self.hasher.chunking.max_chunk_size();
+        let mut offset = 0;
+        while self.tail.len() - offset >= max_chunk_size {
+            let rest = &self.tail[offset..offset + max_chunk_size];
+            let len = self.hasher.chunking.next_cut(rest);
+            let chunk = Chunk {
+                data: Bytes::copy_from_slice(&rest[..len]),
+                leaf_idx: self.chunks.len(),
+            };
+
+            self.leaf_hashes.push(self.hasher.leaf_hash(&chunk));
+            self.chunks.push(chunk);
+            offset += len;
+        }
+        self.tail.drain(..offset);
+    }
+
+    /// Appends everything the reader has until it returns no more bytes, e.g. the bytes written
+    /// to a file since the last call. Returns the number of appended bytes.
+    pub async fn read_from<R>(&mut self, reader: &mut R) -> Result<u64, FileError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut buf = vec![0; READ_BYTES];
+        let mut appended = 0;
+        loop {
+            let bytes = reader.read(&mut buf).await.map_err(|_| FileError::File)?;
+            if bytes == 0 {
+                return Ok(appended);
+            }
+
+            self.append(&buf[..bytes]);
+            appended += bytes as u64;
+        }
+    }
+
+    /// Builds a file of the bytes appended so far, the final chunks are shared with it.
+    pub fn snapshot(&mut self) -> Result<File, FileError> {
+        let mut chunks = self.chunks.clone();
+        let mut leaf_hashes = self.leaf_hashes.clone();
+        for mut chunk in File::to_chunks(&Bytes::copy_from_slice(&self.tail), &self.hasher.chunking)
+        {
+            chunk.leaf_idx = chunks.len();
+            leaf_hashes.push(self.hasher.leaf_hash(&chunk));
+            chunks.push(chunk);
+        }
+
+        let len = self.byte_len();
+        let tree = match &self.tree {
+            Some(tree) => tree.with_leaf_hashes(leaf_hashes, len)?,
+            None => ChunkMerkleTree::from_leaf_hashes(self.hasher, leaf_hashes, len)?,
+        };
+        self.tree = Some(tree.clone());
+
+        Ok(File::from_storage(ChunkStorage::Memory(chunks), tree))
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_growing_file() {
+        use super::*;
+        use crate::{test_cdc_params, test_data};
+        use std::io::Write;
+
+        let data = test_data(30_000);
+
+        // Appends of any size give the roots of files built at once, for both chunkings.
+        let builders = [
+            File::builder().chunk_size(1000),
+            File::builder().content_defined(test_cdc_params()),
+        ];
+        for builder in builders {
+            let mut growing = builder.growing().unwrap();
+            assert_eq!(growing.snapshot().unwrap().get_size(), 0);
+
+            let mut end = 0;
+            for step in [1, 999, 1, 2500, 7000, 18_499] {
+                growing.append(&data[end..end + step]);
+                end += step;
+
+                let snapshot = growing.snapshot().unwrap();
+                let built = builder.build(&data[..end]).unwrap();
+                assert_eq!(snapshot.get_root().unwrap(), built.get_root().unwrap());
+            }
+        }
+
+        // Tail a file on disk while it's written.
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let mut writer = std::fs::File::create(&path).unwrap();
+        let mut source = tokio::fs::File::open(&path).await.unwrap();
+        let mut growing = File::builder().chunk_size(1000).growing().unwrap();
+
+        writer.write_all(&data[..12_345]).unwrap();
+        assert_eq!(growing.read_from(&mut source).await.unwrap(), 12_345);
+        let first = growing.snapshot().unwrap();
+        assert_eq!(growing.read_from(&mut source).await.unwrap(), 0);
+
+        writer.write_all(&data[12_345..]).unwrap();
+        assert_eq!(growing.read_from(&mut source).await.unwrap(), 17_655);
+        let second = growing.snapshot().unwrap();
+        assert_eq!(second.byte_len(), 30_000);
+        assert_eq!(
+            first.changed_leaves(&second).unwrap(),
+            (12..30).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            first.get_chunk(5).unwrap().0.data,
+            second.get_chunk(5).unwrap().0.data
+        );
+    }
+}
@@ -1,5 +1,37 @@
+mod block;
+mod builder;
+mod checkpoint;
 mod chunk;
+mod chunking;
+mod compression;
+mod delta;
+mod encryption;
+mod erasure;
+#[allow(clippy::module_inception)]
 mod file;
+mod growing;
+mod partial;
+mod progress;
+mod range;
+mod recheck;
+mod storage;
+mod stream;
 
+pub use block::*;
+pub use builder::*;
+pub use checkpoint::*;
 pub use chunk::*;
+pub use chunking::*;
+pub use compression::*;
+pub use delta::*;
+pub use encryption::*;
+pub use erasure::*;
 pub use file::*;
+pub use growing::*;
+pub use partial::*;
+pub use progress::*;
+pub use range::*;
+pub use recheck::*;
+pub(crate) use storage::ChunkStorage;
+pub use storage::{DiskFile, MmapFile};
+pub use stream::*;
@@ -0,0 +1,381 @@
+use std::fs;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    root_from_partial, AnyHash, Bitfield, CdcParams, Chunk, ChunkHasher, Chunking, FileError,
+    FileMeta, Piece,
+};
+
+/// The receiving side of a file: it reassembles a file from pieces that are verified against a
+/// trusted root.
+///
+/// Pieces can be added in any order. Every piece is verified with [`root_from_partial`] before
+/// its data is written to the destination file at the chunk offset. The file root commits to the
+/// exact length of the file, so every chunk must have its exact length and the reassembled file
+/// is the same byte for byte. Files with content-defined chunking are created with
+/// [`PartialFile::create_with_lengths`].
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{File, FileRepo, PartialFile};
+///
+/// let data = vec![42u8; 5000];
+/// let file = File::new(&data).unwrap();
+/// let root = file.get_root().unwrap();
+/// let (pieces, len, chunk_size) = (file.get_size(), file.byte_len(), file.chunk_size());
+///
+/// let mut repo = FileRepo::default();
+/// repo.add(file).unwrap();
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let mut partial = PartialFile::create(dir.path().join("out"), root.clone(), len, chunk_size)
+///     .unwrap();
+/// for idx in (0..pieces).rev() {
+///     let piece = repo.get_piece(root.to_hex(), idx).unwrap();
+///     partial.add_piece(idx, &piece).unwrap();
+/// }
+///
+/// let path = partial.finish().unwrap();
+/// assert_eq!(std::fs::read(path).unwrap(), data);
+/// ```
+pub struct PartialFile {
+    root: AnyHash,
+    hasher: ChunkHasher,
+    meta: FileMeta,
+    present: Bitfield,
+    /// Start offsets of variable length chunks followed by the file length.
+    offsets: Option<Vec<u64>>,
+    path: PathBuf,
+    dest: fs::File,
+}
+
+impl PartialFile {
+    /// Creates (or truncates) the destination file for a file with the provided root, length in
+    /// bytes and chunk size. The hash algorithm is taken from the root.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        root: AnyHash,
+        len: u64,
+        chunk_size: usize,
+    ) -> Result<Self, FileError> {
+        if chunk_size == 0 {
+            return Err(FileError::ChunkSize);
+        }
+
+        let hasher = ChunkHasher::new(root.algorithm(), chunk_size);
+        let meta = FileMeta::fixed(len, chunk_size);
+        Self::create_with_hasher(path.as_ref(), root, hasher, meta, None)
+    }
+
+    /// Creates the destination file for a file with content-defined chunking, the parameters
+    /// and the chunk lengths come from the file metadata (see [`crate::File::chunk_lengths`]).
+    pub fn create_with_lengths<P: AsRef<Path>>(
+        path: P,
+        root: AnyHash,
+        params: CdcParams,
+        chunk_lengths: &[usize],
+    ) -> Result<Self, FileError> {
+        if chunk_lengths.iter().any(|l| *l == 0 || *l > params.max) {
+            return Err(FileError::ChunkSize);
+        }
+
+        let mut offsets = vec![0];
+        for len in chunk_lengths {
+            offsets.push(offsets[offsets.len() - 1] + *len as u64);
+        }
+
+        let hasher = ChunkHasher {
+            algorithm: root.algorithm(),
+            chunking: Chunking::ContentDefined(params),
+        };
+        let meta = FileMeta {
+            len: offsets[offsets.len() - 1],
+            chunk_count: chunk_lengths.len(),
+        };
+        Self::create_with_hasher(path.as_ref(), root, hasher, meta, Some(offsets))
+    }
+
+    fn create_with_hasher(
+        path: &Path,
+        root: AnyHash,
+        hasher: ChunkHasher,
+        meta: FileMeta,
+        offsets: Option<Vec<u64>>,
+    ) -> Result<Self, FileError> {
+        let path = path.to_path_buf();
+        let dest = fs::File::create(&path).map_err(|_| FileError::File)?;
+
+        Ok(Self {
+            root,
+            hasher,
+            meta,
+            present: Bitfield::new(meta.chunk_count),
+            offsets,
+            path,
+            dest,
+        })
+    }
+
+    pub fn root(&self) -> &AnyHash {
+        &self.root
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.meta.chunk_count
+    }
+
+    /// Length of the complete file in bytes.
+    pub fn byte_len(&self) -> u64 {
+        self.meta.len
+    }
+
+    /// Lengths of the chunks if the partial file was created with
+    /// [`PartialFile::create_with_lengths`].
+    pub fn chunk_lengths(&self) -> Option<Vec<usize>> {
+        self.offsets
+            .as_ref()
+            .map(|offsets| offsets.windows(2).map(|w| (w[1] - w[0]) as usize).collect())
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.hasher.chunk_size()
+    }
+
+    pub fn chunking(&self) -> Chunking {
+        self.hasher.chunking
+    }
+
+    /// Verifies a piece that was requested for chunk `idx` and writes it to the destination.
+    ///
+    /// A compressed piece is decompressed first, `FileError::Encoding` is returned if that fails.
+    /// Returns `false` if the chunk was already present, `FileError::ChunkSize` if the piece
+    /// doesn't have the expected length and `FileError::Proof` if it doesn't match the root.
+    pub fn add_piece(&mut self, idx: usize, piece: &Piece) -> Result<bool, FileError> {
+        let chunk = piece.decompress(self.chunking().max_chunk_size())?;
+        self.add_chunk(idx, &chunk.data, piece.proof.clone())
+    }
+
+    /// Same as [`PartialFile::add_piece`], but takes the chunk data and proof directly.
+    pub fn add_chunk(
+        &mut self,
+        idx: usize,
+        data: &[u8],
+        proof: Vec<AnyHash>,
+    ) -> Result<bool, FileError> {
+        if idx >= self.chunk_count() {
+            return Err(FileError::File);
+        }
+        let (offset, len) = match &self.offsets {
+            Some(offsets) => (offsets[idx], (offsets[idx + 1] - offsets[idx]) as usize),
+            None => {
+                let offset = idx as u64 * self.chunk_size() as u64;
+                let len = (self.meta.len - offset).min(self.chunk_size() as u64);
+                (offset, len as usize)
+            }
+        };
+        if data.len() != len {
+            return Err(FileError::ChunkSize);
+        }
+        if self.present.test(idx) {
+            return Ok(false);
+        }
+
+        let chunk = Chunk {
+            data: data.to_vec().into(),
+            leaf_idx: idx,
+        };
+        let root = root_from_partial(&self.hasher, &self.meta, &chunk, idx, proof)
+            .map_err(|_| FileError::Proof)?;
+        if root != self.root {
+            return Err(FileError::Proof);
+        }
+
+        self.dest
+            .seek(SeekFrom::Start(offset))
+            .and_then(|_| self.dest.write_all(data))
+            .map_err(|_| FileError::File)?;
+        self.present.set(idx);
+
+        Ok(true)
+    }
+
+    pub fn has(&self, idx: usize) -> bool {
+        self.present.test(idx)
+    }
+
+    /// Indices of the chunks that are not verified and written yet.
+    pub fn missing(&self) -> impl Iterator<Item = usize> + '_ {
+        self.present.iter_zeros()
+    }
+
+    /// The live set of chunks that are verified and written.
+    pub fn bitfield(&self) -> &Bitfield {
+        &self.present
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.present.is_full()
+    }
+
+    /// Flushes the destination file and returns its path. Returns `FileError::Incomplete` if
+    /// some chunks are still missing.
+    pub fn finish(self) -> Result<PathBuf, FileError> {
+        if !self.is_complete() {
+            return Err(FileError::Incomplete);
+        }
+
+        // The destination can be longer if it already existed, e.g. when a download is resumed.
+        self.dest
+            .set_len(self.meta.len)
+            .and_then(|_| self.dest.sync_all())
+            .map_err(|_| FileError::File)?;
+
+        Ok(self.path)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_partial_file_out_of_order() {
+        use super::*;
+        use crate::{File, HashAlgorithm};
+
+        let data: Vec<u8> = (0..9000u32).map(|i| (i % 13) as u8).collect();
+        let file = File::builder()
+            .hash(HashAlgorithm::Sha384)
+            .chunk_size(2000)
+            .build(&data)
+            .unwrap();
+        let root = file.get_root().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut partial = PartialFile::create(dir.path().join("out"), root, 9000, 2000).unwrap();
+
+        for idx in [3, 0, 4, 1] {
+            let (chunk, proof) = file.get_chunk(idx).unwrap();
+            assert!(partial.add_chunk(idx, &chunk.data, proof).unwrap());
+        }
+        assert!(!partial.is_complete());
+        assert_eq!(partial.missing().collect::<Vec<usize>>(), vec![2]);
+        assert_eq!(partial.bitfield().to_bytes(), vec![0b1101_1000]);
+
+        let (chunk, proof) = file.get_chunk(3).unwrap();
+        assert!(!partial.add_chunk(3, &chunk.data, proof).unwrap());
+
+        let (chunk, proof) = file.get_chunk(2).unwrap();
+        assert!(partial.add_chunk(2, &chunk.data, proof).unwrap());
+        assert!(partial.has(2));
+        assert!(partial.bitfield().is_full());
+
+        let path = partial.finish().unwrap();
+        assert_eq!(fs::read(path).unwrap(), data);
+    }
+
+    #[test]
+    fn test_partial_file_with_lengths() {
+        use super::*;
+        use crate::{test_cdc_params, test_data, File};
+
+        let data = test_data(30_000);
+        let params = test_cdc_params();
+        let file = File::builder()
+            .content_defined(params)
+            .build(&data)
+            .unwrap();
+        let lengths = file.chunk_lengths().unwrap().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        let mut partial =
+            PartialFile::create_with_lengths(&path, file.get_root().unwrap(), params, &lengths)
+                .unwrap();
+        assert_eq!(partial.chunk_lengths(), Some(lengths.clone()));
+
+        // A chunk that doesn't have the recorded length is rejected before it's verified.
+        let (chunk, proof) = file.get_chunk(1).unwrap();
+        let res = partial.add_chunk(1, &chunk.data[1..], proof.clone());
+        assert!(matches!(res, Err(FileError::ChunkSize)));
+
+        for idx in (0..lengths.len()).rev() {
+            let (chunk, proof) = file.get_chunk(idx).unwrap();
+            assert!(partial.add_chunk(idx, &chunk.data, proof).unwrap());
+        }
+
+        let path = partial.finish().unwrap();
+        assert_eq!(fs::read(path).unwrap(), data);
+    }
+
+    #[test]
+    fn test_partial_file_rejects_bad_pieces() {
+        use super::*;
+        use crate::File;
+
+        let data: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+        let file = File::new(&data).unwrap();
+        let root = file.get_root().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut partial = PartialFile::create(dir.path().join("out"), root, 3000, 1024).unwrap();
+
+        let (chunk, proof) = file.get_chunk(1).unwrap();
+        let mut tampered = chunk.data.to_vec();
+        tampered[0] ^= 1;
+        let res = partial.add_chunk(1, &tampered, proof.clone());
+        assert!(matches!(res, Err(FileError::Proof)));
+
+        // A valid piece for another index.
+        let res = partial.add_chunk(0, &chunk.data, proof.clone());
+        assert!(matches!(res, Err(FileError::Proof)));
+
+        let res = partial.add_chunk(1, &chunk.data, proof[1..].to_vec());
+        assert!(matches!(res, Err(FileError::Proof)));
+
+        assert!(!partial.has(0) && !partial.has(1));
+        assert!(matches!(partial.finish(), Err(FileError::Incomplete)));
+    }
+
+    #[test]
+    fn test_partial_file_compressed_pieces() {
+        use super::*;
+        use crate::{Compression, File, FileRepo};
+
+        let data = b"compressible text ".repeat(300);
+        let file = File::new(&data).unwrap();
+        let (root, len, pieces) = (file.get_root().unwrap(), file.byte_len(), file.get_size());
+
+        let mut repo = FileRepo::new(Compression::Deflate);
+        repo.add(file).unwrap();
+        assert!(repo.store_stats().compressed_bytes() > 0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut partial =
+            PartialFile::create(dir.path().join("out"), root.clone(), len, 1024).unwrap();
+
+        // A compressed piece that doesn't decode or inflates beyond the chunk size is rejected.
+        let mut piece = repo.get_piece(root.to_hex(), 0).unwrap();
+        piece.encoding = Compression::Deflate;
+        assert!(matches!(
+            partial.add_piece(0, &piece),
+            Err(FileError::Encoding)
+        ));
+        piece.content.data = Compression::Deflate.compress(&[0u8; 1025]);
+        assert!(matches!(
+            partial.add_piece(0, &piece),
+            Err(FileError::Encoding)
+        ));
+
+        for idx in 0..pieces {
+            let piece = repo
+                .get_piece(root.to_hex(), idx)
+                .unwrap()
+                .compress(Compression::Deflate);
+            assert_eq!(piece.encoding, Compression::Deflate);
+            assert!(piece.content.len() < 1024);
+            assert!(partial.add_piece(idx, &piece).unwrap());
+        }
+
+        let path = partial.finish().unwrap();
+        assert_eq!(fs::read(path).unwrap(), data);
+    }
+}
@@ -0,0 +1,73 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// The step of building a [`crate::File`] that an [`IngestProgress`] reports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IngestPhase {
+    /// Chunks are read and their leaves are hashed.
+    Reading,
+    /// All chunks are hashed and the merkle tree is built from the leaf hashes.
+    BuildingTree,
+    /// The file is built.
+    Done,
+}
+
+/// A progress update of [`crate::FileBuilder::from_reader_with_progress`],
+/// [`crate::FileBuilder::open_with_progress`] and their checkpointed variants, reported after
+/// every chunk and on every change of the phase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IngestProgress {
+    pub phase: IngestPhase,
+    pub bytes_read: u64,
+    pub chunks_hashed: usize,
+    /// Length of the file if it's known up front, e.g. from the metadata of a file on disk.
+    pub total_bytes: Option<u64>,
+}
+
+/// Stops the ingestion of a file, e.g. from another task or thread, once it's cancelled.
+///
+/// Clones share the same state, the ingestion checks it before every read and after every chunk
+/// and returns `FileError::Cancelled`.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{CancellationToken, File, FileError};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let data = vec![1u8; 10_000];
+/// let cancel = CancellationToken::new();
+/// let mut updates = 0;
+/// let result = File::builder()
+///     .from_reader_with_progress(&data[..], &cancel, |progress| {
+///         updates += 1;
+///         if progress.bytes_read >= 4096 {
+///             cancel.cancel();
+///         }
+///     })
+///     .await;
+///
+/// assert!(matches!(result, Err(FileError::Cancelled)));
+/// assert_eq!(updates, 4);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
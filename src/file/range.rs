@@ -0,0 +1,171 @@
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::merkle;
+use crate::{AnyHash, Chunk, ChunkHasher, Chunking, FileError, FileMeta};
+
+/// A span of bytes of a file with the chunks that cover it and a single range proof for them,
+/// see [`crate::File::read_at`].
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::File;
+///
+/// let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+/// let file = File::new(&data).unwrap();
+/// let root = file.get_root().unwrap();
+///
+/// // Bytes 1000..3100 are in the chunks 0 to 3.
+/// let range = file.read_at(1000, 2100).unwrap();
+/// assert_eq!((range.first_chunk, range.chunks.len()), (0, 4));
+///
+/// let bytes = range.verify(&file.hasher(), &file.meta(), &root, None).unwrap();
+/// assert_eq!(bytes, &data[1000..3100]);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RangePiece {
+    /// Offset of the first requested byte in the file.
+    pub offset: u64,
+    /// Number of requested bytes.
+    pub len: usize,
+    /// Index of the first chunk that covers the span.
+    pub first_chunk: usize,
+    pub chunks: Vec<Chunk>,
+    pub proof: Vec<AnyHash>,
+}
+
+impl RangePiece {
+    /// Verifies the chunks against the root and returns the requested bytes.
+    ///
+    /// Where the chunks are in the file is calculated from trusted data, like the shape of a
+    /// block tree in [`crate::verify_block`]: for fixed size chunking from the chunk size, for
+    /// content-defined chunking from the list of chunk lengths, which has to be provided.
+    /// Returns `FileError::ChunkSize` if a chunk doesn't have its exact length or the chunks
+    /// don't cover the span and `FileError::Proof` if they don't match the root.
+    pub fn verify(
+        &self,
+        hasher: &ChunkHasher,
+        meta: &FileMeta,
+        root: &AnyHash,
+        chunk_lengths: Option<&[usize]>,
+    ) -> Result<Bytes, FileError> {
+        let end = self.first_chunk + self.chunks.len();
+        if self.chunks.is_empty() || end > meta.chunk_count {
+            return Err(FileError::Proof);
+        }
+
+        let (chunk_offset, lengths) = match (hasher.chunking, chunk_lengths) {
+            (Chunking::Fixed(chunk_size), _) => {
+                let offset = self.first_chunk as u64 * chunk_size as u64;
+                let lengths = (self.first_chunk..end)
+                    .map(|idx| Ok(*meta.chunk_len(hasher, idx)?.start()))
+                    .collect::<Result<Vec<usize>, FileError>>()?;
+                (offset, lengths)
+            }
+            (Chunking::ContentDefined(_), Some(lengths)) => {
+                let total: u64 = lengths.iter().map(|&l| l as u64).sum();
+                if lengths.len() != meta.chunk_count || total != meta.len {
+                    return Err(FileError::ChunkSize);
+                }
+                let offset = lengths[..self.first_chunk].iter().map(|&l| l as u64).sum();
+                (offset, lengths[self.first_chunk..end].to_vec())
+            }
+            (Chunking::ContentDefined(_), None) => return Err(FileError::ChunkSize),
+        };
+
+        let chunk_end = chunk_offset + lengths.iter().map(|&l| l as u64).sum::<u64>();
+        let span_end = self
+            .offset
+            .checked_add(self.len as u64)
+            .ok_or(FileError::ChunkSize)?;
+        if self
+            .chunks
+            .iter()
+            .map(Chunk::len)
+            .ne(lengths.iter().copied())
+            || self.offset < chunk_offset
+            || span_end > chunk_end
+        {
+            return Err(FileError::ChunkSize);
+        }
+
+        let leaf_hashes = self.chunks.iter().map(|c| hasher.leaf_hash(c)).collect();
+        let leaf_count = meta.chunk_count.next_power_of_two();
+        let tree_root = merkle::root_from_range(
+            hasher,
+            leaf_hashes,
+            self.first_chunk,
+            leaf_count,
+            self.proof.clone(),
+        )
+        .map_err(|_| FileError::Proof)?;
+        if &hasher.file_root(meta, &tree_root) != root {
+            return Err(FileError::Proof);
+        }
+
+        let data: Vec<u8> = self.chunks.iter().flat_map(|c| c.data.to_vec()).collect();
+        let start = (self.offset - chunk_offset) as usize;
+        Ok(Bytes::from(data).slice(start..start + self.len))
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_range_piece() {
+        use super::*;
+        use crate::{test_cdc_params, test_data, File};
+
+        let data = test_data(20_000);
+        let file = File::builder().chunk_size(3000).build(&data).unwrap();
+        let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+
+        for (offset, len) in [(0, 1), (2999, 2), (5000, 15_000), (19_999, 1), (0, 20_000)] {
+            let range = file.read_at(offset, len).unwrap();
+            let bytes = range.verify(&hasher, &meta, &root, None).unwrap();
+            assert_eq!(bytes, &data[offset as usize..offset as usize + len]);
+        }
+        assert!(file.read_at(19_999, 2).is_err());
+        assert!(file.read_at(100, 0).is_err());
+        assert_eq!(file.chunk_offset(6).unwrap(), 18_000);
+
+        // A shifted span, a tampered chunk and a zero padded last chunk are rejected.
+        let range = file.read_at(7000, 500).unwrap();
+        let shifted = RangePiece {
+            offset: 1000,
+            ..range.clone()
+        };
+        assert!(matches!(
+            shifted.verify(&hasher, &meta, &root, None),
+            Err(FileError::ChunkSize)
+        ));
+        let mut tampered = range.clone();
+        let mut chunk = tampered.chunks[0].data.to_vec();
+        chunk[0] ^= 1;
+        tampered.chunks[0].data = chunk.into();
+        assert!(matches!(
+            tampered.verify(&hasher, &meta, &root, None),
+            Err(FileError::Proof)
+        ));
+        let mut last = file.read_at(19_000, 10).unwrap();
+        let mut chunk = last.chunks[0].data.to_vec();
+        chunk.push(0);
+        last.chunks[0].data = chunk.into();
+        assert!(last.verify(&hasher, &meta, &root, None).is_err());
+
+        // Content-defined chunks are located with the list of chunk lengths.
+        let file = File::builder()
+            .content_defined(test_cdc_params())
+            .build(&data)
+            .unwrap();
+        let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+        let lengths = file.chunk_lengths().unwrap().unwrap();
+        let range = file.read_at(12_345, 3000).unwrap();
+        assert_eq!(
+            range.verify(&hasher, &meta, &root, Some(&lengths)).unwrap(),
+            &data[12_345..15_345]
+        );
+        assert!(range.verify(&hasher, &meta, &root, None).is_err());
+        let offset = file.chunk_offset(range.first_chunk).unwrap();
+        assert!(offset <= 12_345 && offset + lengths[range.first_chunk] as u64 > 12_345);
+    }
+}
@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::builder::read_chunks;
+use crate::{AnyHash, CancellationToken, ChunkHasher, ChunkMerkleTree, File, FileError, FileMeta};
+
+/// The published file a recheck compares the data on disk with.
+///
+/// A target that is created from a [`File`] has the leaf hashes of its tree, so the chunks that
+/// don't match can be located. A target with only a root (e.g. from a manifest entry) tells
+/// whether the whole file matches.
+#[derive(Clone, Debug)]
+pub struct RecheckTarget {
+    root: AnyHash,
+    hasher: ChunkHasher,
+    meta: FileMeta,
+    leaf_hashes: Option<Vec<AnyHash>>,
+}
+
+impl RecheckTarget {
+    /// A target with a root, the hasher and the meta that are needed to rebuild it.
+    pub fn new(root: AnyHash, hasher: ChunkHasher, meta: FileMeta) -> Self {
+        Self {
+            root,
+            hasher,
+            meta,
+            leaf_hashes: None,
+        }
+    }
+
+    /// A target with the leaf hashes of a stored tree.
+    pub fn from_file(file: &File) -> Result<Self, FileError> {
+        Ok(Self {
+            root: file.get_root()?,
+            hasher: file.hasher(),
+            meta: file.meta(),
+            leaf_hashes: Some(file.tree().leaf_hashes().to_vec()),
+        })
+    }
+
+    pub fn root(&self) -> &AnyHash {
+        &self.root
+    }
+}
+
+/// The result of [`recheck`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecheckReport {
+    /// True if the data on disk has the root of the target.
+    pub matching: bool,
+    /// The root of the data on disk.
+    pub root: AnyHash,
+    /// Chunks of the target that are on disk, but don't match. `None` if the target has no leaf
+    /// hashes to compare the chunks with.
+    pub corrupted: Option<Vec<usize>>,
+    /// Bytes of the target that are missing at the end of the data on disk.
+    pub missing_bytes: u64,
+    /// Bytes on disk after the end of the target.
+    pub extra_bytes: u64,
+}
+
+/// Streams the file at `path` through the chunker of the target and compares every leaf with it.
+/// Only a single chunk and the leaf hashes are kept in memory.
+///
+/// Chunks are compared by their position, so for content-defined chunking an inserted or removed
+/// byte shows up as a corrupted chunk and the following chunks until the cuts line up again.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{recheck, File, RecheckTarget};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("data");
+/// let mut data = vec![1u8; 5000];
+/// let target = RecheckTarget::from_file(&File::new(&data).unwrap()).unwrap();
+///
+/// data[2100] = 0;
+/// data.truncate(4500);
+/// std::fs::write(&path, &data).unwrap();
+///
+/// let report = recheck(&path, &target).await.unwrap();
+/// assert!(!report.matching);
+/// assert_eq!(report.corrupted, Some(vec![2, 4]));
+/// assert_eq!(report.missing_bytes, 500);
+/// # }
+/// ```
+pub async fn recheck<P: AsRef<Path>>(
+    path: P,
+    target: &RecheckTarget,
+) -> Result<RecheckReport, FileError> {
+    let hasher = target.hasher;
+    hasher.chunking.validate()?;
+
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|_| FileError::File)?;
+    let mut leaf_hashes = Vec::new();
+    let mut corrupted = Vec::new();
+    let mut len = 0;
+    read_chunks(file, &hasher.chunking, &CancellationToken::new(), |chunk| {
+        let idx = chunk.leaf_idx;
+        let leaf_hash = hasher.leaf_hash(&chunk);
+        len += chunk.len() as u64;
+
+        if let Some(expected) = target.leaf_hashes.as_ref().and_then(|h| h.get(idx)) {
+            // The last chunk can be truncated to a length that pads to the same leaf hash.
+            let valid_len = target.meta.chunk_len(&hasher, idx);
+            if expected != &leaf_hash || !valid_len.is_ok_and(|l| l.contains(&chunk.len())) {
+                corrupted.push(idx);
+            }
+        }
+        leaf_hashes.push(leaf_hash);
+        Ok(())
+    })
+    .await?;
+
+    let tree = ChunkMerkleTree::from_leaf_hashes(hasher, leaf_hashes, len)?;
+    let root = tree.root()?;
+    Ok(RecheckReport {
+        matching: root == target.root,
+        root,
+        corrupted: target.leaf_hashes.as_ref().map(|_| corrupted),
+        missing_bytes: target.meta.len.saturating_sub(len),
+        extra_bytes: len.saturating_sub(target.meta.len),
+    })
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_recheck() {
+        use super::*;
+        use crate::{test_cdc_params, test_data};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data");
+        let data = test_data(20_000);
+        let file = File::builder().chunk_size(4096).build(&data).unwrap();
+        let target = RecheckTarget::from_file(&file).unwrap();
+
+        std::fs::write(&path, &data).unwrap();
+        let report = recheck(&path, &target).await.unwrap();
+        assert!(report.matching);
+        assert_eq!(&report.root, target.root());
+        assert_eq!(report.corrupted, Some(vec![]));
+
+        // Trailing zero bytes pad the last chunk to the same leaf hash, but not to the same root.
+        let mut extended = data.clone();
+        extended.extend_from_slice(&[0u8; 10]);
+        std::fs::write(&path, &extended).unwrap();
+        let report = recheck(&path, &target).await.unwrap();
+        assert!(!report.matching);
+        assert_eq!(report.corrupted, Some(vec![4]));
+        assert_eq!((report.missing_bytes, report.extra_bytes), (0, 10));
+
+        // Without leaf hashes only the root is compared.
+        let root_only = RecheckTarget::new(file.get_root().unwrap(), file.hasher(), file.meta());
+        let report = recheck(&path, &root_only).await.unwrap();
+        assert!(!report.matching);
+        assert_eq!(report.corrupted, None);
+        std::fs::write(&path, &data).unwrap();
+        assert!(recheck(&path, &root_only).await.unwrap().matching);
+
+        // A flipped byte in content-defined chunks.
+        let builder = File::builder().content_defined(test_cdc_params());
+        let target = RecheckTarget::from_file(&builder.build(&data).unwrap()).unwrap();
+        let mut flipped = data.clone();
+        flipped[10_000] ^= 1;
+        std::fs::write(&path, &flipped).unwrap();
+        let report = recheck(&path, &target).await.unwrap();
+        assert_eq!(report.corrupted.unwrap().len(), 1);
+
+        assert!(recheck(dir.path().join("missing"), &target).await.is_err());
+    }
+}
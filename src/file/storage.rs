@@ -0,0 +1,345 @@
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+use memmap2::Mmap;
+
+use crate::{Chunk, FileError, PersistedChunks, StoredChunks};
+
+/// Where the bytes of [`crate::File`] chunks are kept.
+#[derive(Clone, Debug)]
+pub(crate) enum ChunkStorage {
+    Memory(Vec<Chunk>),
+    Disk(DiskFile),
+    Mmap(MmapFile),
+
+    /// Chunks that are moved to the shared chunk store of a [`crate::FileRepo`].
+    Stored(StoredChunks),
+
+    /// Chunks that are read from the chunks directory of a repo opened with
+    /// [`crate::FileRepo::open`].
+    Persisted(PersistedChunks),
+}
+
+impl ChunkStorage {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Memory(chunks) => chunks.len(),
+            Self::Disk(file) => file.chunk_count(),
+            Self::Mmap(file) => file.disk.chunk_count(),
+            Self::Stored(stored) => stored.keys.len(),
+            Self::Persisted(persisted) => persisted.len(),
+        }
+    }
+
+    pub fn chunk_lengths(&self) -> Result<Vec<usize>, FileError> {
+        match self {
+            Self::Memory(chunks) => Ok(chunks.iter().map(Chunk::len).collect()),
+            Self::Disk(file) => Ok(file.chunk_lengths()),
+            Self::Mmap(file) => Ok(file.disk.chunk_lengths()),
+            Self::Stored(_) => (0..self.len())
+                .map(|idx| self.get(idx).map(|c| c.len()))
+                .collect(),
+            Self::Persisted(persisted) => Ok(persisted.chunk_lengths()),
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Result<Chunk, FileError> {
+        match self {
+            Self::Memory(chunks) => chunks.get(idx).cloned().ok_or(FileError::File),
+            Self::Disk(file) => file.read_chunk(idx),
+            Self::Mmap(file) => file.read_chunk(idx),
+            Self::Stored(stored) => stored.get(idx),
+            Self::Persisted(persisted) => persisted.get(idx),
+        }
+    }
+}
+
+/// A file on disk whose chunks are read on demand.
+///
+/// Only the path, the length and the modification time of the file are kept in memory. Before
+/// and after every read the file metadata is compared with the recorded one, so a file that was
+/// changed after its merkle tree was built yields [`FileError::Changed`] instead of chunks that
+/// don't match the tree.
+///
+/// Content-defined chunks have different lengths, so the start offsets of all chunks are kept
+/// as well.
+#[derive(Clone, Debug)]
+pub struct DiskFile {
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    chunk_size: usize,
+    offsets: Option<Arc<Vec<u64>>>,
+}
+
+impl DiskFile {
+    pub(crate) fn new(path: PathBuf, metadata: &fs::Metadata, chunk_size: usize) -> Self {
+        Self {
+            path,
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            chunk_size,
+            offsets: None,
+        }
+    }
+
+    /// Sets the start offsets of variable length chunks.
+    pub(crate) fn with_offsets(mut self, offsets: Vec<u64>) -> Self {
+        self.offsets = Some(Arc::new(offsets));
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Modification time of the file at the time its merkle tree was built.
+    pub(crate) fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Length of the file in bytes at the time its merkle tree was built.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        match &self.offsets {
+            Some(offsets) => offsets.len(),
+            None => self.len.div_ceil(self.chunk_size as u64) as usize,
+        }
+    }
+
+    pub fn chunk_lengths(&self) -> Vec<usize> {
+        (0..self.chunk_count())
+            .filter_map(|idx| self.chunk_range(idx).map(|(_, len)| len))
+            .collect()
+    }
+
+    /// Offset and length of the chunk at the provided idx.
+    fn chunk_range(&self, idx: usize) -> Option<(u64, usize)> {
+        if idx >= self.chunk_count() {
+            return None;
+        }
+
+        let (offset, end) = match &self.offsets {
+            Some(offsets) => (
+                offsets[idx],
+                offsets.get(idx + 1).copied().unwrap_or(self.len),
+            ),
+            None => {
+                let offset = idx as u64 * self.chunk_size as u64;
+                (offset, self.len.min(offset + self.chunk_size as u64))
+            }
+        };
+        Some((offset, (end - offset) as usize))
+    }
+
+    /// Seeks to the chunk at the provided idx and reads it from disk.
+    pub fn read_chunk(&self, idx: usize) -> Result<Chunk, FileError> {
+        let (offset, len) = self.chunk_range(idx).ok_or(FileError::File)?;
+
+        let mut file = fs::File::open(&self.path).map_err(|_| FileError::File)?;
+        self.check(&file)?;
+
+        let mut data = vec![0; len];
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|_| FileError::File)?;
+        file.read_exact(&mut data).map_err(|_| FileError::Changed)?;
+
+        // The file could have been modified while it was being read.
+        self.check(&file)?;
+
+        Ok(Chunk {
+            data: data.into(),
+            leaf_idx: idx,
+        })
+    }
+
+    /// Returns [`FileError::Changed`] if the size or the modification time of the file differ
+    /// from the ones recorded when the merkle tree was built.
+    pub(crate) fn check(&self, file: &fs::File) -> Result<(), FileError> {
+        let metadata = file.metadata().map_err(|_| FileError::File)?;
+        if metadata.len() != self.len || metadata.modified().ok() != self.modified {
+            return Err(FileError::Changed);
+        }
+        Ok(())
+    }
+}
+
+/// A memory mapped file whose chunks are handed out as slices of the mapping without copying.
+///
+/// Like [`DiskFile`] it compares the file metadata with the recorded one when a chunk is
+/// requested. Since the returned chunks borrow the mapping, a modification that happens after
+/// the check is not detected, so the source file must not be modified while it is served.
+///
+/// A shared advisory lock is held on the file while it's mapped, so writers that take an
+/// exclusive lock wait until it's dropped. The lock doesn't stop other writers: if the file is
+/// truncated, reading a mapped page past the new end kills the process with `SIGBUS` instead of
+/// returning [`FileError::Changed`]. Use [`DiskFile`] for files that can change while served.
+#[derive(Clone, Debug)]
+pub struct MmapFile {
+    disk: DiskFile,
+    file: Arc<fs::File>,
+    data: Bytes,
+}
+
+impl MmapFile {
+    pub(crate) fn open(path: PathBuf, chunk_size: usize) -> Result<Self, FileError> {
+        let file = fs::File::open(&path).map_err(|_| FileError::File)?;
+        let metadata = file.metadata().map_err(|_| FileError::File)?;
+        file.try_lock_shared().map_err(|_| FileError::File)?;
+
+        // Safety: the mapping is read only and the file metadata is checked on every chunk
+        // request, but the slices of the mapping are only valid while the file isn't modified.
+        // Writers that respect the shared lock can't modify it, a truncation by any other writer
+        // raises `SIGBUS` when a page past the new end is read, see the type documentation.
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|_| FileError::File)?;
+
+        Ok(Self {
+            disk: DiskFile::new(path, &metadata, chunk_size),
+            file: Arc::new(file),
+            data: Bytes::from_owner(mmap),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.disk.path()
+    }
+
+    pub(crate) fn with_offsets(mut self, offsets: Vec<u64>) -> Self {
+        self.disk = self.disk.with_offsets(offsets);
+        self
+    }
+
+    pub(crate) fn disk(&self) -> &DiskFile {
+        &self.disk
+    }
+
+    /// The whole mapped file.
+    pub fn data(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Returns the chunk at the provided idx as a slice of the mapping.
+    pub fn read_chunk(&self, idx: usize) -> Result<Chunk, FileError> {
+        let (offset, len) = self.disk.chunk_range(idx).ok_or(FileError::File)?;
+        self.disk.check(&self.file)?;
+
+        let offset = offset as usize;
+        Ok(Chunk {
+            data: self.data.slice(offset..offset + len),
+            leaf_idx: idx,
+        })
+    }
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_disk_file() {
+        use crate::{File, HashAlgorithm};
+        use std::io::Write;
+
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&data).unwrap();
+
+        let file = File::builder()
+            .hash(HashAlgorithm::Sha384)
+            .chunk_size(4096)
+            .open(tmp.path())
+            .await
+            .unwrap();
+        let in_memory = File::builder()
+            .hash(HashAlgorithm::Sha384)
+            .chunk_size(4096)
+            .build(&data)
+            .unwrap();
+
+        assert_eq!(file.get_size(), 3);
+        assert_eq!(file.get_root().unwrap(), in_memory.get_root().unwrap());
+
+        for idx in 0..3 {
+            let (chunk, proof) = file.get_chunk(idx).unwrap();
+            let (expected_chunk, expected_proof) = in_memory.get_chunk(idx).unwrap();
+            assert_eq!(chunk.data, expected_chunk.data);
+            assert_eq!(chunk.leaf_idx, idx);
+            assert_eq!(proof, expected_proof);
+        }
+        assert!(file.get_chunk(3).is_err());
+
+        let rehashed = file.rehash(HashAlgorithm::Sha256).unwrap();
+        let expected = in_memory.rehash(HashAlgorithm::Sha256).unwrap();
+        assert_eq!(rehashed.get_root().unwrap(), expected.get_root().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_disk_file_changed() {
+        use crate::{File, FileError};
+        use std::io::Write;
+        use std::time::{Duration, SystemTime};
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&[1u8; 3000]).unwrap();
+        let file = File::open(tmp.path()).await.unwrap();
+        assert!(file.get_chunk(0).is_ok());
+
+        // Same size, but a different modification time.
+        let modified = SystemTime::now() - Duration::from_secs(3600);
+        tmp.as_file().set_modified(modified).unwrap();
+        assert!(matches!(file.get_chunk(0), Err(FileError::Changed)));
+
+        let file = File::open(tmp.path()).await.unwrap();
+        tmp.write_all(&[2u8; 10]).unwrap();
+        assert!(matches!(file.get_chunk(0), Err(FileError::Changed)));
+    }
+
+    #[test]
+    fn test_mmap_file() {
+        use crate::{File, FileError, FileRepo};
+        use std::io::Write;
+
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&data).unwrap();
+
+        let file = File::builder().chunk_size(2048).mmap(tmp.path()).unwrap();
+        let in_memory = File::builder().chunk_size(2048).build(&data).unwrap();
+        assert_eq!(file.get_size(), 3);
+        assert_eq!(file.get_root().unwrap(), in_memory.get_root().unwrap());
+
+        // Chunks are slices of the mapping, both when taken from a file and from a repo.
+        let (chunk, _) = file.get_chunk(1).unwrap();
+        let (again, _) = file.get_chunk(1).unwrap();
+        assert_eq!(chunk.data, &data[2048..4096]);
+        assert_eq!(chunk.data.as_ptr(), again.data.as_ptr());
+
+        // Writers that lock the file wait until it's no longer mapped.
+        let writer = std::fs::File::options()
+            .write(true)
+            .open(tmp.path())
+            .unwrap();
+        assert!(writer.try_lock().is_err());
+
+        let root = file.get_root().unwrap().to_hex();
+        let mut repo = FileRepo::default();
+        repo.add(file).unwrap();
+        let piece = repo.get_piece(root.clone(), 1).unwrap();
+        assert_eq!(piece.content.data.as_ptr(), chunk.data.as_ptr());
+
+        tmp.write_all(&[1]).unwrap();
+        assert!(matches!(
+            repo.get_piece(root, 1),
+            Err(crate::RepoError::File(FileError::Changed))
+        ));
+    }
+}
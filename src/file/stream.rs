@@ -0,0 +1,181 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{future, Stream, StreamExt};
+
+use crate::{root_from_partial, AnyHash, ChunkHasher, FileError, FileMeta, Piece};
+
+/// Where [`verified_chunks`] fetches pieces from, e.g. a peer or an HTTP server.
+pub trait PieceSource {
+    /// Fetches the piece of the chunk at `idx`. The piece is verified by the caller.
+    fn fetch_piece(&self, idx: usize) -> impl Future<Output = Result<Piece, FileError>> + Send;
+}
+
+/// A shared source, e.g. a peer that several downloads fetch from.
+impl<S: PieceSource> PieceSource for Arc<S> {
+    fn fetch_piece(&self, idx: usize) -> impl Future<Output = Result<Piece, FileError>> + Send {
+        self.as_ref().fetch_piece(idx)
+    }
+}
+
+/// Fetches every piece of a file from the source, verifies it against the trusted root and
+/// returns a stream of the chunk data in file order.
+///
+/// Up to `lookahead` pieces (at least one) are fetched concurrently, a chunk is returned as soon
+/// as it and all chunks before it are verified. The stream ends after the first error: the error
+/// of the source, `FileError::Encoding` if a compressed piece can't be decoded,
+/// `FileError::ChunkSize` if a chunk doesn't have a valid length or `FileError::Proof` if it
+/// doesn't match the root.
+///
+/// # Examples:
+/// ```
+/// use futures::StreamExt;
+/// use pmtorrent::{verified_chunks, File, FileError, Piece, PieceSource};
+///
+/// struct Local(File);
+///
+/// impl PieceSource for Local {
+///     async fn fetch_piece(&self, idx: usize) -> Result<Piece, FileError> {
+///         let (content, proof) = self.0.get_chunk(idx)?;
+///         Ok(Piece { content, proof, encoding: Default::default() })
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let data = vec![5u8; 3000];
+/// let file = File::new(&data).unwrap();
+/// let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+///
+/// let chunks: Vec<_> = verified_chunks(Local(file), hasher, meta, root, 2)
+///     .collect()
+///     .await;
+/// let received: Vec<u8> = chunks.into_iter().flat_map(|c| c.unwrap().to_vec()).collect();
+/// assert_eq!(received, data);
+/// # }
+/// ```
+pub fn verified_chunks<S: PieceSource>(
+    source: S,
+    hasher: ChunkHasher,
+    meta: FileMeta,
+    root: AnyHash,
+    lookahead: usize,
+) -> impl Stream<Item = Result<Bytes, FileError>> {
+    let source = Arc::new(source);
+    futures::stream::iter(0..meta.chunk_count)
+        .map(move |idx| {
+            let source = source.clone();
+            async move { (idx, source.fetch_piece(idx).await) }
+        })
+        .buffered(lookahead.max(1))
+        .map(move |(idx, piece)| verify_piece(&hasher, &meta, &root, idx, piece?))
+        .scan(false, |failed, res| {
+            let res = match *failed {
+                true => None,
+                false => {
+                    *failed = res.is_err();
+                    Some(res)
+                }
+            };
+            future::ready(res)
+        })
+}
+
+fn verify_piece(
+    hasher: &ChunkHasher,
+    meta: &FileMeta,
+    root: &AnyHash,
+    idx: usize,
+    piece: Piece,
+) -> Result<Bytes, FileError> {
+    let chunk = piece.decompress(hasher.chunking.max_chunk_size())?;
+    if &root_from_partial(hasher, meta, &chunk, idx, piece.proof)? != root {
+        return Err(FileError::Proof);
+    }
+    Ok(chunk.data)
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_verified_chunks() {
+        use super::*;
+        use crate::{test_cdc_params, test_data, Compression, File};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Serves the pieces of a file, flips a byte of one chunk and counts the pieces that
+        /// are fetched at the same time.
+        struct Source {
+            file: File,
+            corrupted: Option<usize>,
+            fetching: AtomicUsize,
+            max_fetching: AtomicUsize,
+        }
+
+        impl PieceSource for Source {
+            async fn fetch_piece(&self, idx: usize) -> Result<Piece, FileError> {
+                let fetching = self.fetching.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_fetching.fetch_max(fetching, Ordering::SeqCst);
+                tokio::task::yield_now().await;
+                self.fetching.fetch_sub(1, Ordering::SeqCst);
+
+                let (mut content, proof) = self.file.get_chunk(idx)?;
+                if self.corrupted == Some(idx) {
+                    let mut data = content.data.to_vec();
+                    data[0] ^= 1;
+                    content.data = data.into();
+                }
+                let piece = Piece {
+                    content,
+                    proof,
+                    encoding: Compression::None,
+                };
+                Ok(piece.compress(Compression::Deflate))
+            }
+        }
+
+        let data = test_data(20_000);
+        let builders = [
+            File::builder().chunk_size(1000),
+            File::builder().content_defined(test_cdc_params()),
+        ];
+        for builder in builders {
+            let file = builder.build(&data).unwrap();
+            let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+            let source = Source {
+                file,
+                corrupted: None,
+                fetching: AtomicUsize::new(0),
+                max_fetching: AtomicUsize::new(0),
+            };
+
+            let chunks: Vec<_> = verified_chunks(source, hasher, meta, root, 4)
+                .collect()
+                .await;
+            assert_eq!(chunks.len(), meta.chunk_count);
+            let received: Vec<u8> = chunks
+                .into_iter()
+                .flat_map(|c| c.unwrap().to_vec())
+                .collect();
+            assert_eq!(received, data);
+        }
+
+        // The stream ends with the error of the first chunk that fails its proof.
+        let file = File::builder().chunk_size(1000).build(&data).unwrap();
+        let (hasher, meta, root) = (file.hasher(), file.meta(), file.get_root().unwrap());
+        let source = Arc::new(Source {
+            file,
+            corrupted: Some(5),
+            fetching: AtomicUsize::new(0),
+            max_fetching: AtomicUsize::new(0),
+        });
+
+        let chunks: Vec<_> = verified_chunks(source.clone(), hasher, meta, root, 3)
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 6);
+        assert!(chunks[..5].iter().all(Result::is_ok));
+        assert!(matches!(chunks[5], Err(FileError::Proof)));
+        assert_eq!(source.max_fetching.load(Ordering::SeqCst), 3);
+    }
+}
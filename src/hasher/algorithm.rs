@@ -0,0 +1,270 @@
+use std::fmt::{self, Debug, Display};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    decode_base32, decode_hex, encode_base32, encode_hex, AsBytes, HashError, HashValue, Hasher,
+    Sha256Hash, Sha256Hasher, Sha384Hash, Sha384Hasher, Sha512Hash, Sha512Hasher,
+};
+
+/// Hash algorithms that can be selected at runtime for a [`crate::File`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 3] = [Self::Sha256, Self::Sha384, Self::Sha512];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    /// Length of the hash values produced by the algorithm in bytes.
+    pub fn hash_len(&self) -> usize {
+        match self {
+            Self::Sha256 => Sha256Hash::LEN,
+            Self::Sha384 => Sha384Hash::LEN,
+            Self::Sha512 => Sha512Hash::LEN,
+        }
+    }
+
+    pub fn hasher(&self) -> AnyHasher {
+        AnyHasher(*self)
+    }
+
+    /// A hash value of the algorithm that has all bytes set to zero.
+    pub fn zero_hash(&self) -> AnyHash {
+        match self {
+            Self::Sha256 => AnyHash::Sha256(Sha256Hash::default()),
+            Self::Sha384 => AnyHash::Sha384(Sha384Hash::default()),
+            Self::Sha512 => AnyHash::Sha512(Sha512Hash::default()),
+        }
+    }
+
+    fn from_hash_len(len: usize) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.hash_len() == len)
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = HashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|a| a.name().eq_ignore_ascii_case(s))
+            .ok_or(HashError::Algorithm)
+    }
+}
+
+/// A hasher that dispatches to the [`HashAlgorithm`] selected at runtime.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AnyHasher(pub HashAlgorithm);
+
+impl AnyHasher {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.0
+    }
+}
+
+impl Hasher for AnyHasher {
+    type Hash = AnyHash;
+
+    fn digest(&self, data: &[u8]) -> AnyHash {
+        match self.0 {
+            HashAlgorithm::Sha256 => AnyHash::Sha256(Sha256Hasher.digest(data)),
+            HashAlgorithm::Sha384 => AnyHash::Sha384(Sha384Hasher.digest(data)),
+            HashAlgorithm::Sha512 => AnyHash::Sha512(Sha512Hasher.digest(data)),
+        }
+    }
+}
+
+/// A hash value produced by [`AnyHasher`].
+///
+/// It's formatted as hex and parsed from hex or base32 like a [`HashValue`], the algorithm is
+/// inferred from the length of the parsed value.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum AnyHash {
+    Sha256(Sha256Hash),
+    Sha384(Sha384Hash),
+    Sha512(Sha512Hash),
+}
+
+impl AnyHash {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        match self {
+            Self::Sha256(_) => HashAlgorithm::Sha256,
+            Self::Sha384(_) => HashAlgorithm::Sha384,
+            Self::Sha512(_) => HashAlgorithm::Sha512,
+        }
+    }
+
+    /// Creates a hash value from bytes, the algorithm is picked by the slice length.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+        let algorithm = HashAlgorithm::from_hash_len(bytes.len()).ok_or(HashError::Length)?;
+        Self::from_slice_with(algorithm, bytes)
+    }
+
+    pub fn from_slice_with(algorithm: HashAlgorithm, bytes: &[u8]) -> Result<Self, HashError> {
+        Ok(match algorithm {
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256Hash::from_slice(bytes)?),
+            HashAlgorithm::Sha384 => Self::Sha384(Sha384Hash::from_slice(bytes)?),
+            HashAlgorithm::Sha512 => Self::Sha512(Sha512Hash::from_slice(bytes)?),
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        encode_hex(self.as_bytes())
+    }
+
+    /// Lowercase, unpadded RFC 4648 base32 representation, see [`HashValue::to_base32`].
+    pub fn to_base32(&self) -> String {
+        encode_base32(self.as_bytes())
+    }
+
+    /// A visual fingerprint of the hash value, see [`crate::randomart`].
+    pub fn randomart(&self) -> String {
+        match self {
+            Self::Sha256(h) => h.randomart(),
+            Self::Sha384(h) => h.randomart(),
+            Self::Sha512(h) => h.randomart(),
+        }
+    }
+}
+
+impl Default for AnyHash {
+    fn default() -> Self {
+        HashAlgorithm::default().zero_hash()
+    }
+}
+
+impl AsBytes for AnyHash {
+    fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Sha256(h) => h.as_bytes(),
+            Self::Sha384(h) => h.as_bytes(),
+            Self::Sha512(h) => h.as_bytes(),
+        }
+    }
+}
+
+impl From<Sha256Hash> for AnyHash {
+    fn from(h: Sha256Hash) -> Self {
+        Self::Sha256(h)
+    }
+}
+
+impl Display for AnyHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256(h) => Display::fmt(h, f),
+            Self::Sha384(h) => Display::fmt(h, f),
+            Self::Sha512(h) => Display::fmt(h, f),
+        }
+    }
+}
+
+impl Debug for AnyHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sha256(h) => Debug::fmt(h, f),
+            Self::Sha384(h) => Debug::fmt(h, f),
+            Self::Sha512(h) => Debug::fmt(h, f),
+        }
+    }
+}
+
+impl FromStr for AnyHash {
+    type Err = HashError;
+
+    /// Parses hex or base32 like [`HashValue::parse_str`], hex lengths of all algorithms differ
+    /// from their base32 lengths.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_hex = HashAlgorithm::ALL
+            .iter()
+            .any(|a| s.len() == a.hash_len() * 2);
+        let bytes = match is_hex {
+            true => decode_hex(s),
+            false => decode_base32(s),
+        };
+        Self::from_slice(&bytes.ok_or(HashError::Encoding)?)
+    }
+}
+
+impl Serialize for AnyHash {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        s.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for AnyHash {
+    fn deserialize<D>(d: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(d)?;
+        s.parse()
+            .map_err(|e| serde::de::Error::custom(format!("invalid hash value {:?}: {:?}", s, e)))
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_any_hasher() {
+        use super::*;
+
+        for algorithm in HashAlgorithm::ALL {
+            let hash = algorithm.hasher().digest(b"abc");
+            assert_eq!(hash.algorithm(), algorithm);
+            assert_eq!(hash.as_bytes().len(), algorithm.hash_len());
+            assert_eq!(hash.to_string().parse::<AnyHash>().unwrap(), hash);
+            assert_eq!(hash.to_base32().parse::<AnyHash>().unwrap(), hash);
+            assert_eq!(algorithm.to_string().parse(), Ok(algorithm));
+        }
+
+        assert_eq!(
+            AnyHasher(HashAlgorithm::Sha256).digest(b"abc"),
+            AnyHash::Sha256(Sha256Hasher.digest(b"abc"))
+        );
+        assert_eq!("md5".parse::<HashAlgorithm>(), Err(HashError::Algorithm));
+    }
+
+    #[test]
+    fn test_any_hash_serde() {
+        use super::*;
+
+        let hashes: Vec<AnyHash> = HashAlgorithm::ALL
+            .iter()
+            .map(|a| a.hasher().digest(b"abc"))
+            .collect();
+        let json = serde_json::to_string(&hashes).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<AnyHash>>(&json).unwrap(), hashes);
+
+        // Base32 roots are accepted like they are for `Sha256Hash`.
+        let base32 = format!("\"{}\"", hashes[0].to_base32());
+        assert_eq!(serde_json::from_str::<AnyHash>(&base32).unwrap(), hashes[0]);
+        assert!("abc".parse::<AnyHash>().is_err());
+
+        let json = serde_json::to_string(&HashAlgorithm::Sha384).unwrap();
+        assert_eq!(json, "\"sha384\"");
+    }
+}
@@ -1,12 +1,14 @@
 use std::fmt::{self, Debug};
 
-use crate::{AsBytes, Hasher};
+use crate::{AsBytes, HashError, HashValue, Hasher};
+
+use super::hash_value;
 
 /// 🖖 Emoji hash is a fun part of this project.
 ///
 /// This type is mostly used for MerkleTree trait default implementation (the tests can be found in
 /// `pmtorrent::merkle::dummy` module).
-#[derive(Clone, Default, PartialEq, Eq)]
+#[derive(Clone, Default)]
 pub struct EmojiHash {
     hash: [u8; 4],
 }
@@ -18,9 +20,9 @@ impl EmojiHash {
     ///
     /// This method is marked as unsafe for more than one reason.
     /// * Even though it's possible to implement u32 conversion to a valid utf16 character with
-    /// additional checks (and in this case the `EmojiHasher::digest` method will always return a
-    /// valid utf16 char), this `unsafe` attribute is used to remind the caller that the whole
-    /// EmojiHasher is just for fun, not for safety and reliability.
+    ///   additional checks (and in this case the `EmojiHasher::digest` method will always return a
+    ///   valid utf16 char), this `unsafe` attribute is used to remind the caller that the whole
+    ///   EmojiHasher is just for fun, not for safety and reliability.
     /// * Some combinations of emoji hashes can appear offensive, use with caution.
     pub unsafe fn emoji(&self) -> char {
         let u_32 = u32::from_be_bytes(self.hash);
@@ -42,6 +44,19 @@ impl AsBytes for EmojiHash {
     }
 }
 
+impl HashValue for EmojiHash {
+    const LEN: usize = 4;
+    const NAME: &'static str = "EMOJI";
+
+    fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+        Ok(Self {
+            hash: bytes.try_into().map_err(|_| HashError::Length)?,
+        })
+    }
+}
+
+hash_value!(EmojiHash);
+
 pub struct EmojiHasher;
 impl Hasher for EmojiHasher {
     type Hash = EmojiHash;
@@ -69,3 +84,18 @@ impl AsBytes for &'static str {
         str::as_bytes(self)
     }
 }
+
+mod tests {
+    #[test]
+    fn test_emoji_hash_parsing() {
+        use super::*;
+
+        let hash = EmojiHasher.digest(b"sparta");
+        assert_eq!(hash.to_string().parse::<EmojiHash>().unwrap(), hash);
+        assert_eq!(hash.to_base32().parse::<EmojiHash>().unwrap(), hash);
+
+        let json = serde_json::to_string(&hash).unwrap();
+        let parsed: EmojiHash = serde_json::from_str(&json).unwrap();
+        assert_eq!(unsafe { parsed.emoji() }, unsafe { hash.emoji() });
+    }
+}
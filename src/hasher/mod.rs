@@ -1,11 +1,156 @@
+mod algorithm;
 mod emoji;
+mod randomart;
 mod sha256;
+mod sha512;
 
+pub use algorithm::*;
 pub use emoji::*;
+pub use randomart::*;
 pub use sha256::*;
+pub use sha512::*;
+
+use crate::{decode_base32, decode_hex, encode_base32, encode_hex, AsBytes};
 
 pub trait Hasher {
     type Hash;
 
     fn digest(&self, data: &[u8]) -> Self::Hash;
 }
+
+/// An error that represents failure when a hash value is being parsed from bytes or text.
+#[derive(Debug, PartialEq, Eq)]
+pub enum HashError {
+    /// An error representing wrong number of bytes provided for a hash value.
+    Length,
+
+    /// An error indicating that the provided text is neither valid hex nor base32.
+    Encoding,
+
+    /// An error indicating that the hash algorithm name is not known.
+    Algorithm,
+}
+
+/// HashValue is a trait for fixed size hash values returned by a [`Hasher`].
+///
+/// It provides default implementations for hex and base32 formatting and parsing and for a
+/// constant time comparison. `Display`, `FromStr`, `PartialEq` and serde implementations can be
+/// derived from it with the `hash_value!` macro.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{HashValue, Hasher, Sha256Hasher, Sha256Hash};
+///
+/// let hash = Sha256Hasher.digest(b"pmtorrent");
+/// let parsed: Sha256Hash = hash.to_base32().parse().unwrap();
+/// assert_eq!(hash, parsed);
+/// ```
+pub trait HashValue: AsBytes + Sized {
+    /// Length of the hash value in bytes.
+    const LEN: usize;
+
+    /// Name of the hash function, it is used as a title of the randomart image.
+    const NAME: &'static str;
+
+    /// Creates a hash value from a slice that has exactly [`HashValue::LEN`] bytes.
+    fn from_slice(bytes: &[u8]) -> Result<Self, HashError>;
+
+    /// Lowercase hex representation of the hash value.
+    fn to_hex(&self) -> String {
+        encode_hex(self.as_bytes())
+    }
+
+    /// Lowercase, unpadded RFC 4648 base32 representation of the hash value.
+    fn to_base32(&self) -> String {
+        encode_base32(self.as_bytes())
+    }
+
+    fn from_hex(s: &str) -> Result<Self, HashError> {
+        Self::from_slice(&decode_hex(s).ok_or(HashError::Encoding)?)
+    }
+
+    fn from_base32(s: &str) -> Result<Self, HashError> {
+        Self::from_slice(&decode_base32(s).ok_or(HashError::Encoding)?)
+    }
+
+    /// Parses either hex or base32 representation, the encoding is picked by the text length.
+    fn parse_str(s: &str) -> Result<Self, HashError> {
+        if s.len() == Self::LEN * 2 {
+            Self::from_hex(s)
+        } else {
+            Self::from_base32(s)
+        }
+    }
+
+    /// A visual fingerprint of the hash value, see [`randomart`].
+    fn randomart(&self) -> String {
+        randomart(self.as_bytes(), Self::NAME)
+    }
+
+    /// Compares two hash values in a time that doesn't depend on their contents.
+    fn ct_eq(&self, other: &Self) -> bool {
+        ring::constant_time::verify_slices_are_equal(self.as_bytes(), other.as_bytes()).is_ok()
+    }
+}
+
+/// Implements `Display` (hex, or randomart in the alternate form), `FromStr` (hex or base32),
+/// constant time `PartialEq`, `Eq`, `Hash` and hex string serde for a type that implements
+/// [`HashValue`].
+macro_rules! hash_value {
+    ($t:ty) => {
+        impl std::fmt::Display for $t {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                if f.alternate() {
+                    f.write_str(&$crate::HashValue::randomart(self))
+                } else {
+                    f.write_str(&$crate::HashValue::to_hex(self))
+                }
+            }
+        }
+
+        impl std::str::FromStr for $t {
+            type Err = $crate::HashError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                <$t as $crate::HashValue>::parse_str(s)
+            }
+        }
+
+        impl PartialEq for $t {
+            fn eq(&self, other: &Self) -> bool {
+                $crate::HashValue::ct_eq(self, other)
+            }
+        }
+
+        impl Eq for $t {}
+
+        impl std::hash::Hash for $t {
+            fn hash<S: std::hash::Hasher>(&self, state: &mut S) {
+                $crate::AsBytes::as_bytes(self).hash(state)
+            }
+        }
+
+        impl serde::Serialize for $t {
+            fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                s.serialize_str(&$crate::HashValue::to_hex(self))
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $t {
+            fn deserialize<D>(d: D) -> Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                let s = String::deserialize(d)?;
+                s.parse().map_err(|e| {
+                    serde::de::Error::custom(format!("invalid hash value {:?}: {:?}", s, e))
+                })
+            }
+        }
+    };
+}
+
+pub(crate) use hash_value;
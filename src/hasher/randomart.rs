@@ -0,0 +1,127 @@
+use std::fmt;
+
+use crate::encode_hex;
+
+const FIELD_WIDTH: usize = 17;
+const FIELD_HEIGHT: usize = 9;
+const SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+/// Renders bytes as an OpenSSH style randomart image using the "drunken bishop" walk.
+///
+/// The bishop starts in the middle of a 17x9 field and makes four moves for every byte, each
+/// move being driven by two bits (starting from the least significant ones). The more often a
+/// cell is visited the denser its symbol gets, `S` and `E` mark the start and end positions.
+///
+/// The image is a visual aid only, e.g. to notice that a root changed. The walk is clamped at the
+/// edges of the field and the symbols saturate, so different hash values can render the same
+/// image. Compare the hex or base32 value of a hash to tell whether two hashes are the same.
+///
+/// # Examples:
+/// ```
+/// use pmtorrent::{randomart, Hasher, Sha256Hasher, AsBytes};
+///
+/// let root = Sha256Hasher.digest(b"pmtorrent");
+/// let art = randomart(root.as_bytes(), "SHA256");
+/// assert_eq!(art.lines().count(), 11);
+/// ```
+pub fn randomart(bytes: &[u8], title: &str) -> String {
+    let mut field = [[0u8; FIELD_WIDTH]; FIELD_HEIGHT];
+    let (mut x, mut y) = (FIELD_WIDTH / 2, FIELD_HEIGHT / 2);
+    let max_visits = (SYMBOLS.len() - 3) as u8;
+
+    for b in bytes {
+        let mut b = *b;
+        for _ in 0..4 {
+            x = if b & 0x1 != 0 {
+                (x + 1).min(FIELD_WIDTH - 1)
+            } else {
+                x.saturating_sub(1)
+            };
+            y = if b & 0x2 != 0 {
+                (y + 1).min(FIELD_HEIGHT - 1)
+            } else {
+                y.saturating_sub(1)
+            };
+
+            let cell = &mut field[y][x];
+            *cell = (*cell + 1).min(max_visits);
+            b >>= 2;
+        }
+    }
+
+    field[FIELD_HEIGHT / 2][FIELD_WIDTH / 2] = SYMBOLS.len() as u8 - 2;
+    field[y][x] = SYMBOLS.len() as u8 - 1;
+
+    let mut art = String::with_capacity((FIELD_WIDTH + 3) * (FIELD_HEIGHT + 2));
+    art.push_str(&border(title));
+    for row in field {
+        art.push('|');
+        art.extend(row.iter().map(|&c| SYMBOLS[c as usize] as char));
+        art.push_str("|\n");
+    }
+    art.push_str(&border(""));
+    art.pop();
+
+    art
+}
+
+fn border(title: &str) -> String {
+    let title = if title.is_empty() {
+        String::new()
+    } else {
+        format!("[{}]", title)
+    };
+    format!("+{:-^width$}+\n", title, width = FIELD_WIDTH)
+}
+
+/// Formats a hash value as `Name(hex)`, the alternate form (`{:#?}`) also renders the randomart.
+pub(crate) fn debug_hash(
+    f: &mut fmt::Formatter<'_>,
+    name: &str,
+    title: &str,
+    bytes: &[u8],
+) -> fmt::Result {
+    write!(f, "{}({})", name, encode_hex(bytes))?;
+    if f.alternate() {
+        write!(f, "\n{}", randomart(bytes, title))?;
+    }
+    Ok(())
+}
+
+mod tests {
+    #[test]
+    fn test_randomart() {
+        use super::*;
+
+        // Every 0x00 byte moves the bishop up-left four times, so it ends in the top left corner.
+        let art = randomart(&[0u8; 4], "ZERO");
+        let expected = [
+            "+-----[ZERO]------+",
+            "|E....            |",
+            "|     .           |",
+            "|      .          |",
+            "|       .         |",
+            "|        S        |",
+            "|                 |",
+            "|                 |",
+            "|                 |",
+            "|                 |",
+            "+-----------------+",
+        ];
+        assert_eq!(art.lines().collect::<Vec<&str>>(), expected);
+
+        let art = randomart(&[0u8; 1], "");
+        assert_eq!(art.lines().nth(1), Some("|    E            |"));
+        assert_eq!(art.lines().nth(4), Some("|       .         |"));
+    }
+
+    #[test]
+    fn test_randomart_uses_all_bytes() {
+        use super::*;
+
+        let mut bytes = [0x5au8; 32];
+        let art = randomart(&bytes, "SHA256");
+        bytes[31] ^= 0xff;
+        assert_ne!(art, randomart(&bytes, "SHA256"));
+    }
+}
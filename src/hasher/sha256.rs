@@ -1,7 +1,8 @@
 use ring::digest;
-use serde::Serializer;
 
-use crate::{encode_hex, AsBytes, Hasher};
+use crate::{AsBytes, HashError, HashValue, Hasher};
+
+use super::{hash_value, randomart::debug_hash};
 
 /// A hasher that hashes provided data with Sha256 algorithm.
 pub struct Sha256Hasher;
@@ -15,7 +16,7 @@ impl Hasher for Sha256Hasher {
     }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+#[derive(Clone, Default)]
 pub struct Sha256Hash([u8; 32]);
 
 impl Sha256Hash {
@@ -30,11 +31,70 @@ impl AsBytes for Sha256Hash {
     }
 }
 
-impl serde::Serialize for Sha256Hash {
-    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        s.serialize_str(&encode_hex(&self.0))
+impl HashValue for Sha256Hash {
+    const LEN: usize = 32;
+    const NAME: &'static str = "SHA256";
+
+    fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+        Ok(Self(bytes.try_into().map_err(|_| HashError::Length)?))
+    }
+}
+
+hash_value!(Sha256Hash);
+
+impl std::fmt::Debug for Sha256Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        debug_hash(f, "Sha256Hash", Self::NAME, &self.0)
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_hex_and_base32_round_trip() {
+        use super::*;
+
+        let hash = Sha256Hasher.digest(b"abc");
+        let hex = hash.to_string();
+        assert_eq!(
+            hex,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(hex.parse::<Sha256Hash>().unwrap(), hash);
+
+        let b32 = hash.to_base32();
+        assert_eq!(b32.len(), 52);
+        assert_eq!(b32.parse::<Sha256Hash>().unwrap(), hash);
+
+        assert_eq!("aaaa".parse::<Sha256Hash>(), Err(HashError::Length));
+        assert_eq!(
+            hex.replace('a', "x").parse::<Sha256Hash>(),
+            Err(HashError::Encoding)
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        use super::*;
+
+        let hash = Sha256Hasher.digest(b"abc");
+        let json = serde_json::to_string(&vec![hash.clone()]).unwrap();
+        let parsed: Vec<Sha256Hash> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, vec![hash]);
+
+        assert!(serde_json::from_str::<Sha256Hash>("\"not a hash\"").is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_formatting() {
+        use super::*;
+
+        let hash = Sha256Hasher.digest(b"abc");
+        let art = format!("{:#}", hash);
+        assert_eq!(art, hash.randomart());
+        assert!(art.starts_with("+----[SHA256]-----+\n"));
+
+        let debug = format!("{:?}", hash);
+        assert_eq!(debug, format!("Sha256Hash({})", hash));
+        assert_eq!(format!("{:#?}", hash), format!("{}\n{}", debug, art));
     }
 }
@@ -0,0 +1,93 @@
+use ring::digest;
+
+use crate::{AsBytes, HashError, HashValue, Hasher};
+
+use super::{hash_value, randomart::debug_hash};
+
+/// A hasher that hashes provided data with Sha384 algorithm.
+pub struct Sha384Hasher;
+
+impl Hasher for Sha384Hasher {
+    type Hash = Sha384Hash;
+
+    fn digest(&self, data: &[u8]) -> Sha384Hash {
+        let h = digest::digest(&digest::SHA384, data);
+        Sha384Hash(h.as_ref().try_into().expect("48 byte value"))
+    }
+}
+
+/// A hasher that hashes provided data with Sha512 algorithm.
+pub struct Sha512Hasher;
+
+impl Hasher for Sha512Hasher {
+    type Hash = Sha512Hash;
+
+    fn digest(&self, data: &[u8]) -> Sha512Hash {
+        let h = digest::digest(&digest::SHA512, data);
+        Sha512Hash(h.as_ref().try_into().expect("64 byte value"))
+    }
+}
+
+#[derive(Clone)]
+pub struct Sha384Hash([u8; 48]);
+
+impl Default for Sha384Hash {
+    fn default() -> Self {
+        Self([0u8; 48])
+    }
+}
+
+impl AsBytes for Sha384Hash {
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl HashValue for Sha384Hash {
+    const LEN: usize = 48;
+    const NAME: &'static str = "SHA384";
+
+    fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+        Ok(Self(bytes.try_into().map_err(|_| HashError::Length)?))
+    }
+}
+
+hash_value!(Sha384Hash);
+
+impl std::fmt::Debug for Sha384Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        debug_hash(f, "Sha384Hash", Self::NAME, &self.0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Sha512Hash([u8; 64]);
+
+impl Default for Sha512Hash {
+    fn default() -> Self {
+        Self([0u8; 64])
+    }
+}
+
+impl AsBytes for Sha512Hash {
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl HashValue for Sha512Hash {
+    const LEN: usize = 64;
+    const NAME: &'static str = "SHA512";
+
+    fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+        Ok(Self(bytes.try_into().map_err(|_| HashError::Length)?))
+    }
+}
+
+hash_value!(Sha512Hash);
+
+impl std::fmt::Debug for Sha512Hash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        debug_hash(f, "Sha512Hash", Self::NAME, &self.0)
+    }
+}
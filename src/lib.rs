@@ -1,15 +1,22 @@
 use std::fmt::Write;
 
+mod bitfield;
+mod directory;
 mod file;
 mod hasher;
 pub mod merkle;
 mod repo;
 
+pub use bitfield::*;
+pub use directory::*;
+pub use file::root_from_partial;
 pub use file::*;
 pub use hasher::*;
 pub use merkle::*;
 pub use repo::*;
 
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
 pub fn encode_hex(bytes: &[u8]) -> String {
     let mut s = String::with_capacity(bytes.len() * 2);
     for &b in bytes {
@@ -18,6 +25,104 @@ pub fn encode_hex(bytes: &[u8]) -> String {
     s
 }
 
+/// Decodes a hex string (either case), returns `None` if the string is not valid hex.
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    // `from_str_radix` alone would accept a sign, e.g. "+f".
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Encodes bytes with RFC 4648 base32 alphabet in lowercase and without padding.
+pub fn encode_base32(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let mut buf = 0u16;
+    let mut bits = 0;
+
+    for &b in bytes {
+        buf = (buf << 8) | b as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            s.push(BASE32_ALPHABET[(buf >> bits) as usize & 31] as char);
+        }
+    }
+
+    if bits > 0 {
+        s.push(BASE32_ALPHABET[(buf << (5 - bits)) as usize & 31] as char);
+    }
+    s
+}
+
+/// Decodes unpadded base32 string (either case), returns `None` if the string is not valid
+/// base32 or has non zero trailing bits.
+pub fn decode_base32(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buf = 0u16;
+    let mut bits = 0;
+
+    for c in s.bytes() {
+        let v = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_lowercase())?;
+        buf = (buf << 5) | v as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buf >> bits) as u8);
+        }
+    }
+
+    if bits >= 5 || buf & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(bytes)
+}
+
 pub trait AsBytes {
     fn as_bytes(&self) -> &[u8];
 }
+
+mod tests {
+    #[test]
+    fn test_hex() {
+        use super::*;
+
+        assert_eq!(encode_hex(&[0, 15, 255]), "000fff");
+        assert_eq!(decode_hex("000fFF"), Some(vec![0, 15, 255]));
+        assert_eq!(decode_hex("0f0"), None);
+        assert_eq!(decode_hex("zz"), None);
+        assert_eq!(decode_hex("+f"), None);
+        assert_eq!(decode_hex("0+"), None);
+    }
+
+    #[test]
+    fn test_base32() {
+        use super::*;
+
+        // Test vectors from RFC 4648, lowercase and without padding.
+        let vectors = [
+            ("", ""),
+            ("f", "my"),
+            ("fo", "mzxq"),
+            ("foo", "mzxw6"),
+            ("foob", "mzxw6yq"),
+            ("fooba", "mzxw6ytb"),
+            ("foobar", "mzxw6ytboi"),
+        ];
+
+        for (plain, encoded) in vectors {
+            assert_eq!(encode_base32(plain.as_bytes()), encoded);
+            assert_eq!(decode_base32(encoded).unwrap(), plain.as_bytes());
+        }
+
+        assert_eq!(decode_base32("MZXQ"), Some(b"fo".to_vec()));
+        assert_eq!(decode_base32("mz"), None);
+        assert_eq!(decode_base32("m1"), None);
+    }
+}
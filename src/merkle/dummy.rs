@@ -87,7 +87,7 @@ mod tests {
         ];
 
         // A dark twist to the emoji merkle tree.
-        let leaves: Vec<&str> = "なぜそんなに真剣なんだ? 🃏".split("").into_iter().collect();
+        let leaves: Vec<&str> = "なぜそんなに真剣なんだ? 🃏".split("").collect();
 
         let dummy_tree = DummyMerkleTree::new(&leaves).expect("valid count of nodes");
 
@@ -111,7 +111,7 @@ mod tests {
         use super::*;
         use crate::merkle::root_from_partial;
 
-        let leaves: Vec<&str> = "💁 💂 💃 💄 💅 💆 👏 📮".split(' ').into_iter().collect();
+        let leaves: Vec<&str> = "💁 💂 💃 💄 💅 💆 👏 📮".split(' ').collect();
         let hasher = EmojiHasher;
 
         let dummy_tree = DummyMerkleTree::new(&leaves).expect("valid count of nodes");
@@ -124,11 +124,105 @@ mod tests {
         assert_eq!(*trusted_root, untrusted_root);
     }
 
+    #[test]
+    fn test_root_from_partial_all_leaves() {
+        use super::*;
+        use crate::merkle::root_from_partial;
+
+        let words: Vec<&str> = "a b c d e f g h i j k l m n o p".split(' ').collect();
+        let hasher = EmojiHasher;
+
+        for leaf_count in [2, 4, 8, 16] {
+            let leaves = &words[..leaf_count];
+            let dummy_tree = DummyMerkleTree::new(leaves).expect("valid count of nodes");
+            let trusted_root = dummy_tree.get_tree().last().unwrap();
+
+            for (idx, leaf) in leaves.iter().enumerate() {
+                let proof_parts = dummy_tree.get_proof_hashes(idx).unwrap();
+                let untrusted_root =
+                    root_from_partial(&hasher, leaf, idx, leaf_count, proof_parts).unwrap();
+                assert_eq!(
+                    *trusted_root, untrusted_root,
+                    "leaf {} of {}",
+                    idx, leaf_count
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_root_from_range() {
+        use super::*;
+        use crate::merkle::root_from_range;
+        use crate::Hasher;
+
+        let words: Vec<&str> = "a b c d e f g h i j k l m n o p".split(' ').collect();
+        let hasher = EmojiHasher;
+        let dummy_tree = DummyMerkleTree::new(&words).expect("valid count of nodes");
+        let trusted_root = dummy_tree.get_tree().last().unwrap();
+
+        for first in 0..16 {
+            for last in first..16 {
+                let proof_parts = dummy_tree.get_range_proof_hashes(first, last).unwrap();
+                let leaf_hashes = words[first..=last]
+                    .iter()
+                    .map(|w| hasher.digest(w.as_bytes()))
+                    .collect();
+                let untrusted_root =
+                    root_from_range(&hasher, leaf_hashes, first, 16, proof_parts).unwrap();
+                assert_eq!(*trusted_root, untrusted_root, "leaves {}..={}", first, last);
+            }
+        }
+
+        // A single leaf has the same proof as with `get_proof_hashes`.
+        assert_eq!(
+            dummy_tree.get_range_proof_hashes(5, 5).unwrap(),
+            dummy_tree.get_proof_hashes(5).unwrap()
+        );
+        assert!(dummy_tree.get_range_proof_hashes(3, 16).is_err());
+
+        let mut proof_parts = dummy_tree.get_range_proof_hashes(3, 9).unwrap();
+        let leaf_hashes: Vec<_> = words[3..=9]
+            .iter()
+            .map(|w| hasher.digest(w.as_bytes()))
+            .collect();
+        let res = root_from_range(&hasher, leaf_hashes.clone(), 10, 16, proof_parts.clone());
+        assert_eq!(res, Err(MerkleError::InvalidIdx));
+        proof_parts.push(proof_parts[0].clone());
+        let res = root_from_range(&hasher, leaf_hashes, 3, 16, proof_parts);
+        assert_eq!(res, Err(MerkleError::LeafCount));
+    }
+
+    #[test]
+    fn test_root_from_partial_malformed_proof() {
+        use super::*;
+        use crate::merkle::root_from_partial;
+        use crate::Hasher;
+
+        let leaves: Vec<&str> = "💁 💂 💃 💄".split(' ').collect();
+        let hasher = EmojiHasher;
+        let dummy_tree = DummyMerkleTree::new(&leaves).expect("valid count of nodes");
+        let mut proof_parts = dummy_tree.get_proof_hashes(1).unwrap();
+
+        let res = root_from_partial(&hasher, &leaves[1], 4, 4, proof_parts.clone());
+        assert_eq!(res, Err(MerkleError::InvalidIdx));
+
+        let res = root_from_partial(&hasher, &leaves[1], 1, 3, proof_parts.clone());
+        assert_eq!(res, Err(MerkleError::LeafCount));
+
+        proof_parts.pop();
+        let res = root_from_partial(&hasher, &leaves[1], 1, 4, proof_parts);
+        assert_eq!(res, Err(MerkleError::LeafCount));
+
+        let root = root_from_partial(&hasher, &leaves[0], 0, 1, vec![]).unwrap();
+        assert_eq!(root, hasher.digest(leaves[0].as_bytes()));
+    }
+
     #[test]
     fn test_leaf_count() {
         use super::*;
 
-        let leaves: Vec<&str> = "💁 💂 💃 💄 💅 💆 👏".split(' ').into_iter().collect();
+        let leaves: Vec<&str> = "💁 💂 💃 💄 💅 💆 👏".split(' ').collect();
         let dummy_tree = DummyMerkleTree::new(&leaves);
         assert_eq!(dummy_tree, Err(MerkleError::LeafCount));
     }
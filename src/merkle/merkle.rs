@@ -79,9 +79,18 @@ where
     /// E.g. If a slice of leaves are provided with `[l1, l2, l3, l4]` as items, the returned tree will
     /// have such layout: `[h_l1, h_l2, h_l3, h_l4, h_p_l12, h_p_l34, h_root]`.
     fn build_tree(hasher: &H, leaves: &[D]) -> Result<Vec<H::Hash>, MerkleError> {
-        let mut tree: Vec<H::Hash> = vec![];
-
         let first_level = Self::build_first_level(hasher, leaves)?;
+        Self::build_tree_from_first_level(hasher, first_level)
+    }
+
+    /// Builds a tree from already hashed first level of nodes, it's used by
+    /// [`MerkleTree::build_tree`] and can be used directly when the leaves are hashed one by one,
+    /// e.g. while they are streamed from a reader.
+    fn build_tree_from_first_level(
+        hasher: &H,
+        first_level: Vec<H::Hash>,
+    ) -> Result<Vec<H::Hash>, MerkleError> {
+        let mut tree: Vec<H::Hash> = vec![];
         let mut current_level = first_level;
 
         // Every level will have two times less nodes than the previous level.
@@ -154,6 +163,43 @@ where
         Ok(hashes)
     }
 
+    /// Provides a minimal set of hashes for the leaves `first..=last` that are needed to calculate
+    /// the hash of a root node, see [`root_from_range`].
+    ///
+    /// From the bottom level up, the left sibling of the first node and the right sibling of the
+    /// last node of the range are added when they're outside of it.
+    fn get_range_proof_hashes(
+        &self,
+        first: usize,
+        last: usize,
+    ) -> Result<Vec<H::Hash>, MerkleError> {
+        let leaf_count = self.get_leaf_count();
+        if first > last || last >= leaf_count {
+            return Err(MerkleError::InvalidIdx);
+        }
+
+        let tree = self.get_tree();
+        let mut hashes = Vec::default();
+        let (mut first, mut last) = (first, last);
+        let (mut offset, mut width) = (0, leaf_count);
+
+        while width > 1 {
+            if !first.is_multiple_of(2) {
+                hashes.push(tree[offset + first - 1].clone());
+            }
+            if last.is_multiple_of(2) {
+                hashes.push(tree[offset + last + 1].clone());
+            }
+
+            offset += width;
+            width /= 2;
+            first /= 2;
+            last /= 2;
+        }
+
+        Ok(hashes)
+    }
+
     /// A method that is used by the default implementation of MerkleTree to retrieve a sibling of a
     /// node at the provided idx.
     fn get_sibling(&self, idx: usize) -> Result<(H::Hash, usize), MerkleError> {
@@ -161,7 +207,11 @@ where
             return Err(MerkleError::InvalidIdx);
         }
 
-        let sibling_idx = if idx % 2 == 0 { idx + 1 } else { idx - 1 };
+        let sibling_idx = if idx.is_multiple_of(2) {
+            idx + 1
+        } else {
+            idx - 1
+        };
         let hash = self
             .get_tree()
             .get(sibling_idx)
@@ -198,7 +248,7 @@ where
     /// A method that uses formula of a perfect complete binary for a leaf count retrieval.
     fn get_leaf_count(&self) -> usize {
         let node_count = self.get_tree().len();
-        (node_count + 1) / 2
+        node_count.div_ceil(2)
     }
 }
 
@@ -208,6 +258,10 @@ where
 /// This method can be wrapped inside a custom `root_from_partial` implementation that modifies the
 /// original data to meet the application specification.
 ///
+/// The proof usually comes from an untrusted party, so `MerkleError::LeafCount` is returned if
+/// the number of hashes doesn't match the height of a tree with `leaf_count` leaves and
+/// `MerkleError::InvalidIdx` if the leaf index is out of bounds.
+///
 /// # Examples:
 /// ```
 /// use pmtorrent::{EmojiHasher, EmojiHash, merkle, MerkleError};
@@ -242,34 +296,105 @@ where
     H: Hasher,
     H::Hash: AsBytes,
 {
-    let node_count = leaf_count * 2 - 1;
-
-    let mut l = &hasher.digest(leaf.as_bytes());
-    let mut r = &hashes[0];
+    root_from_leaf_hash(
+        hasher,
+        hasher.digest(leaf.as_bytes()),
+        leaf_idx,
+        leaf_count,
+        hashes,
+    )
+}
 
-    if leaf_idx % 2 != 0 {
-        std::mem::swap(&mut l, &mut r)
+/// Same as [`root_from_partial`], but starts from the hash of the leaf instead of its data, e.g.
+/// when the leaf hash is the root of another tree.
+pub fn root_from_leaf_hash<H>(
+    hasher: &H,
+    leaf_hash: H::Hash,
+    leaf_idx: usize,
+    leaf_count: usize,
+    hashes: Vec<H::Hash>,
+) -> Result<H::Hash, MerkleError>
+where
+    H: Hasher,
+    H::Hash: AsBytes,
+{
+    if !is_pow_of_two(leaf_count) || hashes.len() != leaf_count.trailing_zeros() as usize {
+        return Err(MerkleError::LeafCount);
+    }
+    if leaf_idx >= leaf_count {
+        return Err(MerkleError::InvalidIdx);
     }
 
-    let mut root_hash = hasher.digest(&[l.as_bytes(), r.as_bytes()].concat());
-    let mut parent_idx = node_count - (node_count - leaf_idx - 1 + leaf_idx % 2) / 2;
+    let node_count = leaf_count * 2 - 1;
+    let mut root_hash = leaf_hash;
+    let mut idx = leaf_idx;
 
-    for h in hashes[1..].iter() {
+    for h in hashes.iter() {
         let mut l = &root_hash;
         let mut r = h;
-        if parent_idx % 2 != 0 {
+        if !idx.is_multiple_of(2) {
             std::mem::swap(&mut l, &mut r);
         }
 
         root_hash = hasher.digest(&[l.as_bytes(), r.as_bytes()].concat());
-        if parent_idx + 2 >= node_count {
-            parent_idx = node_count - (node_count - parent_idx - 1 + parent_idx % 2) / 2;
-        }
+        idx = node_count - (node_count - idx - 1 + idx % 2) / 2;
     }
 
     Ok(root_hash)
 }
 
+/// Calculates the root hash from the hashes of consecutive leaves that start at `first` and the
+/// proof hashes that were calculated via the `get_range_proof_hashes` method.
+///
+/// Like [`root_from_leaf_hash`] it returns `MerkleError::LeafCount` if the number of hashes
+/// doesn't match the range and `MerkleError::InvalidIdx` if the range is empty or out of bounds.
+pub fn root_from_range<H>(
+    hasher: &H,
+    leaf_hashes: Vec<H::Hash>,
+    first: usize,
+    leaf_count: usize,
+    hashes: Vec<H::Hash>,
+) -> Result<H::Hash, MerkleError>
+where
+    H: Hasher,
+    H::Hash: AsBytes,
+{
+    if !is_pow_of_two(leaf_count) {
+        return Err(MerkleError::LeafCount);
+    }
+    if leaf_hashes.is_empty() || first + leaf_hashes.len() > leaf_count {
+        return Err(MerkleError::InvalidIdx);
+    }
+
+    let mut hashes = hashes.into_iter();
+    let mut level = leaf_hashes;
+    let mut first = first;
+    let mut width = leaf_count;
+
+    while width > 1 {
+        let last = first + level.len() - 1;
+        if !first.is_multiple_of(2) {
+            level.insert(0, hashes.next().ok_or(MerkleError::LeafCount)?);
+            first -= 1;
+        }
+        if last.is_multiple_of(2) {
+            level.push(hashes.next().ok_or(MerkleError::LeafCount)?);
+        }
+
+        level = level
+            .chunks(2)
+            .map(|c| hasher.digest(&[c[0].as_bytes(), c[1].as_bytes()].concat()))
+            .collect();
+        first /= 2;
+        width /= 2;
+    }
+
+    if hashes.next().is_some() {
+        return Err(MerkleError::LeafCount);
+    }
+    level.pop().ok_or(MerkleError::LeafCount)
+}
+
 /// Returns true if a number is 2^x.
 pub fn is_pow_of_two(l: usize) -> bool {
     l > 0 && (l & (l - 1)) == 0
@@ -1,4 +1,5 @@
 mod dummy;
+#[allow(clippy::module_inception)]
 mod merkle;
 
 pub use merkle::*;
@@ -0,0 +1,123 @@
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_hex, encode_hex, AnyHash, AsBytes, Hasher};
+
+const ALIAS_DOMAIN: &[u8] = b"pmtorrent-root-alias";
+
+/// A cross-reference between a root of a file hashed with an old algorithm and a root of the
+/// same file rehashed with a new algorithm.
+///
+/// The `commitment` binds both roots together and is calculated with the *old* algorithm, so it
+/// can be recomputed without a new hasher. Anyone can compute a commitment for a root they don't
+/// own, so an alias is only trusted when its Ed25519 signature over the commitment is made by a
+/// key the client already trusts, see [`RootAlias::verify_signed_by`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RootAlias {
+    pub old: AnyHash,
+    pub new: AnyHash,
+    pub commitment: AnyHash,
+    pub signature: AliasSignature,
+}
+
+/// Hex encoded Ed25519 public key and signature over a [`RootAlias::commitment`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AliasSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+impl RootAlias {
+    pub fn new(old: AnyHash, new: AnyHash, signer: &Ed25519KeyPair) -> Self {
+        let commitment = Self::commit(&old, &new);
+        let signature = AliasSignature {
+            public_key: encode_hex(signer.public_key().as_ref()),
+            signature: encode_hex(signer.sign(commitment.as_bytes()).as_ref()),
+        };
+
+        Self {
+            old,
+            new,
+            commitment,
+            signature,
+        }
+    }
+
+    /// Checks that the commitment matches both roots and that the alias is signed by the
+    /// provided public key.
+    pub fn verify_signed_by(&self, public_key: &[u8]) -> bool {
+        decode_hex(&self.signature.public_key).as_deref() == Some(public_key)
+            && Self::commit(&self.old, &self.new) == self.commitment
+            && self.signature.verify(self.commitment.as_bytes())
+    }
+
+    fn commit(old: &AnyHash, new: &AnyHash) -> AnyHash {
+        let message = [
+            ALIAS_DOMAIN,
+            old.algorithm().name().as_bytes(),
+            &[0],
+            old.as_bytes(),
+            new.algorithm().name().as_bytes(),
+            &[0],
+            new.as_bytes(),
+        ]
+        .concat();
+
+        old.algorithm().hasher().digest(&message)
+    }
+}
+
+impl AliasSignature {
+    fn verify(&self, message: &[u8]) -> bool {
+        let (public_key, signature) =
+            match (decode_hex(&self.public_key), decode_hex(&self.signature)) {
+                (Some(p), Some(s)) => (p, s),
+                _ => return false,
+            };
+
+        signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+            .verify(message, &signature)
+            .is_ok()
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_root_alias() {
+        use super::*;
+        use crate::HashAlgorithm;
+        use ring::rand::SystemRandom;
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = key.public_key().as_ref();
+
+        let old = HashAlgorithm::Sha256.hasher().digest(b"old");
+        let new = HashAlgorithm::Sha384.hasher().digest(b"new");
+        let alias = RootAlias::new(old, new, &key);
+        assert_eq!(alias.commitment.algorithm(), HashAlgorithm::Sha256);
+        assert!(alias.verify_signed_by(public_key));
+        assert!(!alias.verify_signed_by(&[0u8; 32]));
+
+        let json = serde_json::to_string(&alias).unwrap();
+        let parsed: RootAlias = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, alias);
+
+        // A forged alias with a recomputed commitment isn't signed by the trusted key.
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let other = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let evil = HashAlgorithm::Sha384.hasher().digest(b"evil");
+        let forged = RootAlias::new(alias.old.clone(), evil.clone(), &other);
+        assert!(!forged.verify_signed_by(public_key));
+
+        let mut swapped = alias.clone();
+        swapped.new = evil;
+        assert!(!swapped.verify_signed_by(public_key));
+
+        let mut tampered = alias;
+        let mut bytes = decode_hex(&tampered.signature.signature).unwrap();
+        bytes[0] ^= 1;
+        tampered.signature.signature = encode_hex(&bytes);
+        assert!(!tampered.verify_signed_by(public_key));
+    }
+}
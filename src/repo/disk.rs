@@ -0,0 +1,659 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AnyHash, Chunk, ChunkHasher, ChunkMerkleTree, ChunkStorage, Chunking, DiskFile, File,
+    FileError, FileMeta, FileVersion, HashAlgorithm, Hasher, Manifest, RootAlias, Sha256Hash,
+    Sha256Hasher,
+};
+
+const INDEX_FILE: &str = "index.json";
+const TREES_DIR: &str = "trees";
+const CHUNKS_DIR: &str = "chunks";
+
+/// The directory of a persistent [`crate::FileRepo`], see [`crate::FileRepo::open`].
+///
+/// The layout is:
+/// - `index.json`: the roots with their hasher, meta and source, the versions, the aliases and
+///   the manifests of directories,
+/// - `trees/<root>.json`: the leaf hashes of a root and the chunk offsets of content-defined
+///   chunks, the rest of the merkle tree is hashed again from the leaves when it's loaded,
+/// - `chunks/<key>`: the chunks of files that were added from memory, keyed like the
+///   [`crate::ChunkStore`] by the Sha256 hash of their data, so versions and files that share
+///   chunks share the files as well. A file that was read from disk records its path and
+///   modification time instead.
+///
+/// Every file is written to a temporary file, synced and renamed, and the directory is synced
+/// after the rename. The index is written last, so an entry is either complete or missing from
+/// the index. Files that aren't referenced by the index are leftovers of an interrupted write or
+/// of removed entries and are removed when the index is saved.
+pub(crate) struct RepoDir {
+    path: PathBuf,
+    entries: HashMap<String, IndexEntry>,
+    /// Chunk keys of the entries that were added from memory.
+    chunks: HashMap<String, Vec<Sha256Hash>>,
+    /// Directories with a file that is kept in the index but can't be served, see
+    /// [`RepoDir::open`].
+    unavailable_directories: Vec<Manifest>,
+}
+
+/// The contents of a [`RepoDir`] that are loaded by [`RepoDir::open`].
+pub(crate) struct RepoContents {
+    pub files: Vec<File>,
+    pub versions: Vec<FileVersion>,
+    pub aliases: Vec<RootAlias>,
+    pub directories: Vec<Manifest>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct RepoIndex {
+    entries: Vec<IndexEntry>,
+    versions: Vec<FileVersion>,
+    aliases: Vec<RootAlias>,
+    #[serde(default)]
+    directories: Vec<Manifest>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct IndexEntry {
+    root: AnyHash,
+    algorithm: HashAlgorithm,
+    chunking: Chunking,
+    meta: FileMeta,
+    source: EntrySource,
+}
+
+/// Where the bytes of an entry are read from.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+enum EntrySource {
+    /// The chunks are in the `chunks` directory of the repo, see [`StoredTree::chunks`].
+    Chunks,
+    /// The file the tree was built from, it's dropped from the repo once it's modified.
+    Path {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTree {
+    leaf_hashes: Vec<AnyHash>,
+    offsets: Option<Vec<u64>>,
+    /// Keys of the chunks of an entry that was added from memory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunks: Option<Vec<Sha256Hash>>,
+}
+
+/// The chunks of a file that was added from memory to a [`RepoDir`], they're read from the
+/// chunks directory on demand.
+#[derive(Clone, Debug)]
+pub(crate) struct PersistedChunks {
+    dir: PathBuf,
+    keys: Arc<Vec<Sha256Hash>>,
+    lengths: Arc<Vec<usize>>,
+}
+
+impl PersistedChunks {
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn chunk_lengths(&self) -> Vec<usize> {
+        self.lengths.to_vec()
+    }
+
+    /// Reads the chunk at the provided idx, returns `FileError::Changed` if its file doesn't
+    /// have the recorded length.
+    pub fn get(&self, idx: usize) -> Result<Chunk, FileError> {
+        let key = self.keys.get(idx).ok_or(FileError::File)?;
+        let data = fs::read(self.dir.join(key.to_string())).map_err(|_| FileError::File)?;
+        if data.len() != self.lengths[idx] {
+            return Err(FileError::Changed);
+        }
+        Ok(Chunk {
+            data: data.into(),
+            leaf_idx: idx,
+        })
+    }
+}
+
+impl IndexEntry {
+    fn hasher(&self) -> ChunkHasher {
+        ChunkHasher {
+            algorithm: self.algorithm,
+            chunking: self.chunking,
+        }
+    }
+}
+
+impl RepoDir {
+    /// Opens or creates the directory and loads every entry.
+    ///
+    /// An entry whose tree is missing, can't be parsed or doesn't hash to its root wasn't
+    /// completely written and is dropped from the index, together with the directories that
+    /// contain it. An entry whose bytes can't be read or were modified (e.g. a missing mount or an
+    /// edited source file) is kept in the index but not served, so it's served again once its
+    /// bytes are back.
+    pub fn open(path: &Path) -> Result<(Self, RepoContents), FileError> {
+        for dir in [TREES_DIR, CHUNKS_DIR] {
+            fs::create_dir_all(path.join(dir)).map_err(|_| FileError::File)?;
+        }
+        sync_dir(path)?;
+
+        let index: RepoIndex = match fs::read(path.join(INDEX_FILE)) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|_| FileError::File)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RepoIndex::default(),
+            Err(_) => return Err(FileError::File),
+        };
+
+        let mut repo_dir = Self {
+            path: path.to_path_buf(),
+            entries: HashMap::new(),
+            chunks: HashMap::new(),
+            unavailable_directories: Vec::new(),
+        };
+        let mut files = Vec::new();
+        let mut served = HashSet::new();
+        for entry in &index.entries {
+            let hash = entry.root.to_hex();
+            let file = match repo_dir.read_tree(entry) {
+                Ok(Some((tree, stored))) => {
+                    if let Some(keys) = &stored.chunks {
+                        repo_dir.chunks.insert(hash.clone(), keys.clone());
+                    }
+                    repo_dir.load(entry, tree, stored)
+                }
+                Ok(None) => continue,
+                Err(e) => Err(e),
+            };
+            if let Ok(file) = file {
+                served.insert(hash.clone());
+                files.push(file);
+            }
+            repo_dir.entries.insert(hash, entry.clone());
+        }
+
+        // A directory is restored if none of its files were dropped and served if all of them
+        // are served.
+        let mut directories = Vec::new();
+        let mut kept_directories = 0;
+        for manifest in &index.directories {
+            let roots: Vec<String> = manifest.entries.iter().map(|e| e.root.to_hex()).collect();
+            if !roots.iter().all(|root| repo_dir.entries.contains_key(root)) {
+                continue;
+            }
+            kept_directories += 1;
+            match roots.iter().all(|root| served.contains(root)) {
+                true => directories.push(manifest.clone()),
+                false => repo_dir.unavailable_directories.push(manifest.clone()),
+            }
+        }
+
+        repo_dir.remove_unreferenced()?;
+        let contents = RepoContents {
+            files,
+            versions: index.versions,
+            aliases: index.aliases,
+            directories,
+        };
+        if repo_dir.entries.len() != index.entries.len()
+            || kept_directories != index.directories.len()
+        {
+            repo_dir.save_index(&contents.versions, &contents.aliases, &contents.directories)?;
+        }
+        Ok((repo_dir, contents))
+    }
+
+    /// Writes the tree and the chunks or the source path of a file. Chunks that are already in
+    /// the chunks directory aren't written again. The file is only part of the repo once the
+    /// index is saved.
+    pub fn add(&mut self, file: &File) -> Result<(), FileError> {
+        let hash = file.get_root()?.to_hex();
+        if self.entries.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let (source, chunks) = match file.storage() {
+            ChunkStorage::Disk(disk) => (source_path(disk), None),
+            ChunkStorage::Mmap(mmap) => (source_path(mmap.disk()), None),
+            storage => {
+                let chunks_dir = self.path.join(CHUNKS_DIR);
+                let keys = (0..storage.len())
+                    .map(|idx| {
+                        let data = storage.get(idx)?.data;
+                        let key = Sha256Hasher.digest(&data);
+                        let path = chunks_dir.join(key.to_string());
+                        let written = fs::metadata(&path).map(|m| m.len()).ok();
+                        if written != Some(data.len() as u64) {
+                            write_file(&path, |w| w.write_all(&data).map_err(|_| FileError::File))?;
+                        }
+                        Ok(key)
+                    })
+                    .collect::<Result<Vec<_>, FileError>>()?;
+                sync_dir(&chunks_dir)?;
+                (EntrySource::Chunks, Some(keys))
+            }
+        };
+
+        let offsets = file.chunk_lengths()?.map(|lengths| {
+            lengths
+                .iter()
+                .scan(0, |offset, &len| {
+                    let start = *offset;
+                    *offset += len as u64;
+                    Some(start)
+                })
+                .collect()
+        });
+        let stored = StoredTree {
+            leaf_hashes: file.tree().leaf_hashes().to_vec(),
+            offsets,
+            chunks,
+        };
+        write_atomic(&self.tree_path(&hash), |w| {
+            serde_json::to_writer(w, &stored).map_err(|_| FileError::File)
+        })?;
+
+        let hasher = file.hasher();
+        let entry = IndexEntry {
+            root: file.get_root()?,
+            algorithm: hasher.algorithm,
+            chunking: hasher.chunking,
+            meta: file.meta(),
+            source,
+        };
+        if let Some(keys) = stored.chunks {
+            self.chunks.insert(hash.clone(), keys);
+        }
+        self.entries.insert(hash, entry);
+        Ok(())
+    }
+
+    /// Drops an entry, its files and the chunks that no other entry uses are removed once the
+    /// index is saved without it.
+    pub fn remove(&mut self, hash: &str) {
+        self.entries.remove(hash);
+        self.chunks.remove(hash);
+    }
+
+    pub fn save_index<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a FileVersion>,
+        aliases: impl IntoIterator<Item = &'a RootAlias>,
+        directories: impl IntoIterator<Item = &'a Manifest>,
+    ) -> Result<(), FileError> {
+        let index = RepoIndex {
+            entries: self.entries.values().cloned().collect(),
+            versions: versions.into_iter().cloned().collect(),
+            aliases: aliases.into_iter().cloned().collect(),
+            directories: directories
+                .into_iter()
+                .cloned()
+                .chain(self.unavailable_directories.iter().cloned())
+                .collect(),
+        };
+        write_atomic(&self.path.join(INDEX_FILE), |w| {
+            serde_json::to_writer(w, &index).map_err(|_| FileError::File)
+        })?;
+
+        // Failing to remove files of dropped entries only leaves them for the next open.
+        let _ = self.remove_unreferenced();
+        Ok(())
+    }
+
+    /// Reads the tree of an entry and hashes its inner nodes from the stored leaf hashes. Returns
+    /// `None` if the tree wasn't completely written: it's missing, can't be parsed or doesn't
+    /// hash to the root of the entry.
+    fn read_tree(
+        &self,
+        entry: &IndexEntry,
+    ) -> Result<Option<(ChunkMerkleTree, StoredTree)>, FileError> {
+        let data = match fs::read(self.tree_path(&entry.root.to_hex())) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(_) => return Err(FileError::File),
+        };
+        let stored: StoredTree = match serde_json::from_slice(&data) {
+            Ok(stored) => stored,
+            Err(_) => return Ok(None),
+        };
+
+        let hasher = entry.hasher();
+        let chunks_complete = match (&entry.source, &stored.chunks) {
+            (EntrySource::Chunks, Some(keys)) => keys.len() == entry.meta.chunk_count,
+            (EntrySource::Path { .. }, None) => true,
+            _ => false,
+        };
+        if !chunks_complete
+            || stored.leaf_hashes.len() != entry.meta.chunk_count
+            || stored
+                .leaf_hashes
+                .iter()
+                .any(|leaf| leaf.algorithm() != hasher.algorithm)
+        {
+            return Ok(None);
+        }
+        let leaf_hashes = stored.leaf_hashes.clone();
+        let tree = ChunkMerkleTree::from_leaf_hashes(hasher, leaf_hashes, entry.meta.len)?;
+        match tree.root()? == entry.root {
+            true => Ok(Some((tree, stored))),
+            false => Ok(None),
+        }
+    }
+
+    /// Restores the file of an entry from its tree without hashing the bytes. Returns
+    /// `FileError::Changed` if the bytes on disk don't have the recorded length or modification
+    /// time.
+    fn load(
+        &self,
+        entry: &IndexEntry,
+        tree: ChunkMerkleTree,
+        stored: StoredTree,
+    ) -> Result<File, FileError> {
+        let hasher = entry.hasher();
+        let offsets = match (stored.offsets, hasher.chunking.is_padded()) {
+            (Some(offsets), false) if offsets.len() == entry.meta.chunk_count => Some(offsets),
+            (None, true) => None,
+            _ => return Err(FileError::File),
+        };
+
+        let (path, modified) = match &entry.source {
+            EntrySource::Chunks => {
+                let keys = stored.chunks.ok_or(FileError::File)?;
+                let chunks = self.load_chunks(entry, keys, offsets)?;
+                return Ok(File::from_storage(ChunkStorage::Persisted(chunks), tree));
+            }
+            EntrySource::Path { path, modified } => (path, modified),
+        };
+        let metadata = fs::metadata(path).map_err(|_| FileError::File)?;
+        if metadata.len() != entry.meta.len || metadata.modified().ok() != *modified {
+            return Err(FileError::Changed);
+        }
+
+        let mut disk_file = DiskFile::new(path.clone(), &metadata, hasher.chunk_size());
+        if let Some(offsets) = offsets {
+            disk_file = disk_file.with_offsets(offsets);
+        }
+        Ok(File::from_storage(ChunkStorage::Disk(disk_file), tree))
+    }
+
+    /// Checks that the chunk files of an entry that was added from memory have the lengths of
+    /// its chunks, which are given by the start offsets of content-defined chunks or by the
+    /// chunk size.
+    fn load_chunks(
+        &self,
+        entry: &IndexEntry,
+        keys: Vec<Sha256Hash>,
+        offsets: Option<Vec<u64>>,
+    ) -> Result<PersistedChunks, FileError> {
+        let chunk_size = entry.hasher().chunk_size() as u64;
+        let starts =
+            offsets.unwrap_or_else(|| (0..keys.len() as u64).map(|i| i * chunk_size).collect());
+        let ends = starts.iter().skip(1).chain([&entry.meta.len]);
+        let lengths = starts
+            .iter()
+            .zip(ends)
+            .map(|(start, end)| end.checked_sub(*start).map(|len| len as usize))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(FileError::File)?;
+
+        let dir = self.path.join(CHUNKS_DIR);
+        for (key, &len) in keys.iter().zip(&lengths) {
+            let metadata = fs::metadata(dir.join(key.to_string())).map_err(|_| FileError::File)?;
+            if metadata.len() != len as u64 {
+                return Err(FileError::Changed);
+            }
+        }
+        Ok(PersistedChunks {
+            dir,
+            keys: Arc::new(keys),
+            lengths: Arc::new(lengths),
+        })
+    }
+
+    fn tree_path(&self, hash: &str) -> PathBuf {
+        self.path.join(TREES_DIR).join(format!("{}.json", hash))
+    }
+
+    /// Removes the files of the trees and chunks directories that don't belong to an entry and
+    /// a temporary index. Chunks are only removed if the chunk keys of every entry are known,
+    /// i.e. the trees of kept entries that were added from memory could be read.
+    fn remove_unreferenced(&self) -> Result<(), FileError> {
+        let trees = self.entries.keys().map(|h| format!("{}.json", h)).collect();
+        remove_other_files(&self.path.join(TREES_DIR), &trees)?;
+
+        let chunks_known = self.entries.iter().all(|(hash, entry)| {
+            !matches!(entry.source, EntrySource::Chunks) || self.chunks.contains_key(hash)
+        });
+        if chunks_known {
+            let chunks = self
+                .entries
+                .keys()
+                .filter_map(|hash| self.chunks.get(hash))
+                .flatten()
+                .map(|key| key.to_string())
+                .collect();
+            remove_other_files(&self.path.join(CHUNKS_DIR), &chunks)?;
+        }
+
+        let tmp_index = self.path.join(INDEX_FILE).with_extension("tmp");
+        if tmp_index.exists() {
+            fs::remove_file(tmp_index).map_err(|_| FileError::File)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes every file of the directory whose name isn't in `keep`.
+fn remove_other_files(dir: &Path, keep: &HashSet<String>) -> Result<(), FileError> {
+    for entry in fs::read_dir(dir).map_err(|_| FileError::File)? {
+        let entry = entry.map_err(|_| FileError::File)?;
+        if !keep.contains(&*entry.file_name().to_string_lossy()) {
+            fs::remove_file(entry.path()).map_err(|_| FileError::File)?;
+        }
+    }
+    Ok(())
+}
+
+fn source_path(disk: &DiskFile) -> EntrySource {
+    let path = disk.path();
+    EntrySource::Path {
+        path: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        modified: disk.modified(),
+    }
+}
+
+/// Writes a temporary file next to `path`, syncs it to disk and renames it to `path`. The parent
+/// directory is synced as well, otherwise the rename itself could be lost in a crash.
+pub(crate) fn write_atomic<F>(path: &Path, write: F) -> Result<(), FileError>
+where
+    F: FnOnce(&mut BufWriter<&fs::File>) -> Result<(), FileError>,
+{
+    write_file(path, write)?;
+    sync_dir(path.parent().ok_or(FileError::File)?)
+}
+
+/// Like [`write_atomic`], but doesn't sync the parent directory, so several files can be written
+/// before it's synced once.
+fn write_file<F>(path: &Path, write: F) -> Result<(), FileError>
+where
+    F: FnOnce(&mut BufWriter<&fs::File>) -> Result<(), FileError>,
+{
+    let tmp = path.with_extension("tmp");
+    let file = fs::File::create(&tmp).map_err(|_| FileError::File)?;
+    let mut writer = BufWriter::new(&file);
+    write(&mut writer)?;
+    writer.flush().map_err(|_| FileError::File)?;
+    drop(writer);
+    file.sync_all().map_err(|_| FileError::File)?;
+    fs::rename(tmp, path).map_err(|_| FileError::File)
+}
+
+/// Syncs the entries of a directory, e.g. a file that was created or renamed in it. Directories
+/// can't be opened as files on every platform, there the entries are synced with the files.
+fn sync_dir(path: &Path) -> Result<(), FileError> {
+    if cfg!(unix) {
+        let dir = fs::File::open(path).map_err(|_| FileError::File)?;
+        dir.sync_all().map_err(|_| FileError::File)?;
+    }
+    Ok(())
+}
+
+mod tests {
+    #[tokio::test]
+    async fn test_repo_dir() {
+        use super::*;
+        use crate::{test_cdc_params, test_data, Compression, Directory, FileRepo};
+
+        let dir = tempfile::tempdir().unwrap();
+        let repo_path = dir.path().join("repo");
+        let source = dir.path().join("source");
+        let data = test_data(20_000);
+        std::fs::write(&source, &data).unwrap();
+
+        let in_memory = File::builder().chunk_size(3000).build(&data).unwrap();
+        let cdc = File::builder()
+            .content_defined(test_cdc_params())
+            .build(&data[..15_000])
+            .unwrap();
+        let on_disk = File::builder()
+            .chunk_size(4096)
+            .open(&source)
+            .await
+            .unwrap();
+        let mut changed = data.clone();
+        changed[100] ^= 1;
+        let version = File::builder().chunk_size(3000).build(&changed).unwrap();
+
+        let hashes: Vec<String> = [&in_memory, &cdc, &on_disk, &version]
+            .iter()
+            .map(|f| f.get_root().unwrap().to_hex())
+            .collect();
+        let pieces: Vec<_> = [&in_memory, &cdc, &on_disk, &version]
+            .iter()
+            .map(|f| f.get_chunk(f.get_size() - 1).unwrap())
+            .collect();
+        let chunk_path = |file: &File, idx| {
+            let key = Sha256Hasher.digest(&file.get_chunk(idx).unwrap().0.data);
+            repo_path.join(CHUNKS_DIR).join(key.to_string())
+        };
+        let in_memory_chunks: Vec<PathBuf> = (0..in_memory.get_size())
+            .map(|idx| chunk_path(&in_memory, idx))
+            .collect();
+        let cdc_chunk = chunk_path(&cdc, 0);
+        let version_chunk = chunk_path(&version, 0);
+        let chunk_count = in_memory.get_size() + cdc.get_size() + 1;
+
+        // A directory with the same content as the content-defined file shares its entry.
+        let cdc_copy = File::builder()
+            .content_defined(test_cdc_params())
+            .build(&data[..15_000])
+            .unwrap();
+        let directory =
+            Directory::from_files(HashAlgorithm::Sha256, vec![("a/cdc".to_string(), cdc_copy)])
+                .unwrap();
+        let directory_root = directory.root().unwrap();
+
+        let mut repo = FileRepo::open(&repo_path, Compression::None).unwrap();
+        repo.add(in_memory).unwrap();
+        repo.add(cdc).unwrap();
+        repo.add(on_disk).unwrap();
+        repo.add_version(&hashes[0], version).unwrap();
+        repo.add_directory(directory).unwrap();
+        drop(repo);
+
+        // The version shares every chunk but the first one with the previous version.
+        let chunk_files = std::fs::read_dir(repo_path.join(CHUNKS_DIR)).unwrap();
+        assert_eq!(chunk_files.count(), chunk_count);
+
+        // The files are restored with their trees and served from disk.
+        let repo = FileRepo::open(&repo_path, Compression::None).unwrap();
+        let mut available: Vec<String> = repo
+            .get_available()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.hash)
+            .collect();
+        available.sort();
+        let mut expected = hashes.clone();
+        expected.sort();
+        assert_eq!(available, expected);
+        for (hash, (chunk, proof)) in hashes.iter().zip(&pieces) {
+            let piece = repo.get_piece(hash.clone(), chunk.leaf_idx).unwrap();
+            assert_eq!(&piece.content.data, &chunk.data);
+            assert_eq!(&piece.proof, proof);
+        }
+        assert_eq!(repo.get_versions(&hashes[3]).len(), 2);
+        let piece = repo
+            .get_directory_piece(&directory_root.to_hex(), "a/cdc", 1)
+            .unwrap();
+        assert!(piece.verify(&directory_root, "a/cdc", 1).is_ok());
+        drop(repo);
+
+        // Leftovers of interrupted writes are removed and an entry whose tree doesn't hash to its
+        // root is dropped. Entries with truncated chunks or a modified source are kept, but not
+        // served, like the directory that contains one of them.
+        let leftovers = [
+            repo_path.join("index.tmp"),
+            repo_path.join(TREES_DIR).join("unknown.tmp"),
+            repo_path.join(CHUNKS_DIR).join("unknown"),
+        ];
+        for leftover in &leftovers {
+            std::fs::write(leftover, b"partial").unwrap();
+        }
+        let tree_path = repo_path
+            .join(TREES_DIR)
+            .join(format!("{}.json", hashes[3]));
+        let mut tree: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&tree_path).unwrap()).unwrap();
+        tree["leaf_hashes"][0] = serde_json::json!(hashes[0]);
+        std::fs::write(&tree_path, tree.to_string()).unwrap();
+
+        let chunks = cdc_chunk;
+        let chunks_data = std::fs::read(&chunks).unwrap();
+        std::fs::write(&chunks, &chunks_data[..10]).unwrap();
+        let source_modified = std::fs::metadata(&source).unwrap().modified().unwrap();
+        std::fs::write(&source, &changed).unwrap();
+
+        let repo = FileRepo::open(&repo_path, Compression::None).unwrap();
+        assert_eq!(repo.get_available().unwrap().len(), 1);
+        assert!(repo.get_piece(hashes[1].clone(), 0).is_err());
+        assert!(repo.get_piece(hashes[2].clone(), 0).is_err());
+        assert!(leftovers.iter().all(|leftover| !leftover.exists()));
+        assert!(!tree_path.exists());
+        assert!(!version_chunk.exists());
+        assert!(in_memory_chunks.iter().all(|chunk| chunk.exists()));
+        assert!(chunks.exists());
+        assert!(repo.get_directories().is_empty());
+        drop(repo);
+
+        // Once the bytes are back, the kept entries and the directory are served again.
+        std::fs::write(&chunks, &chunks_data).unwrap();
+        std::fs::write(&source, &data).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(source_modified)
+            .unwrap();
+
+        let mut repo = FileRepo::open(&repo_path, Compression::None).unwrap();
+        assert_eq!(repo.get_available().unwrap().len(), 3);
+        assert!(repo.get_piece(hashes[2].clone(), 0).is_ok());
+        assert_eq!(repo.get_directories(), vec![directory_root.to_hex()]);
+
+        repo.remove(&hashes[0]).unwrap();
+        assert!(in_memory_chunks.iter().all(|chunk| !chunk.exists()));
+        assert!(chunks.exists());
+        drop(repo);
+        let repo = FileRepo::open(&repo_path, Compression::None).unwrap();
+        assert_eq!(repo.get_available().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,11 @@
+mod alias;
+mod disk;
+#[allow(clippy::module_inception)]
+mod repo;
+mod store;
+
+pub use alias::*;
+pub(crate) use disk::{write_atomic, PersistedChunks, RepoDir};
+pub use repo::*;
+pub(crate) use store::StoredChunks;
+pub use store::{ChunkStore, StoreStats};
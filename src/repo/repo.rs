@@ -0,0 +1,812 @@
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    file::{File, FileError},
+    AnyHash, Bitfield, BlockPiece, Chunk, ChunkStorage, ChunkStore, Chunking, Compression,
+    Directory, DirectoryPiece, FileDelta, HashAlgorithm, Manifest, ManifestTree, MerkleError,
+    PartialFile, RangePiece, RepoDir, RootAlias, StoreStats, StoredChunks,
+};
+
+#[derive(Debug)]
+pub enum RepoError {
+    DoesntExist,
+    File(FileError),
+    /// The root of a new version is already part of the version history, e.g. a file that is
+    /// reverted to an earlier version.
+    VersionExists,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileDescription {
+    pub hash: String,
+    pub hash_algorithm: HashAlgorithm,
+    /// Length of the file in bytes.
+    pub size: u64,
+    pub chunking: Chunking,
+    pub chunk_size: usize,
+    pub pieces: usize,
+    /// Lengths of all pieces for files with content-defined chunking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_lengths: Option<Vec<usize>>,
+    /// Pieces that are available, all of them unless the file is still being downloaded.
+    pub bitfield: Bitfield,
+    pub randomart: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+    /// Root of the previous version of the file, see [`FileRepo::add_version`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+}
+
+/// A version of a file in a [`FileRepo`], the first version has no previous one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FileVersion {
+    pub root: AnyHash,
+    pub previous: Option<AnyHash>,
+    /// Number of the version, the first one is 0.
+    pub version: usize,
+    /// Leaves that changed since the previous version.
+    pub changed: Vec<usize>,
+}
+
+impl From<FileError> for RepoError {
+    fn from(e: FileError) -> Self {
+        match e {
+            FileError::Merkle(MerkleError::InvalidIdx) => RepoError::DoesntExist,
+            _ => RepoError::File(e),
+        }
+    }
+}
+
+/// A chunk with the proof hashes from its leaf to the root.
+///
+/// The content can be compressed on the wire, `encoding` tells how. The proof always covers the
+/// uncompressed chunk, so a piece is decompressed with [`Piece::decompress`] before it's verified.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Piece {
+    pub content: Chunk,
+    pub proof: Vec<AnyHash>,
+    #[serde(default)]
+    pub encoding: Compression,
+}
+
+impl Piece {
+    /// Compresses the content of an uncompressed piece, unless it doesn't get smaller.
+    pub fn compress(self, compression: Compression) -> Self {
+        if compression == Compression::None || self.encoding != Compression::None {
+            return self;
+        }
+
+        let (data, encoding) = compression.compress_smaller(&self.content.data);
+        Self {
+            content: Chunk {
+                data,
+                leaf_idx: self.content.leaf_idx,
+            },
+            proof: self.proof,
+            encoding,
+        }
+    }
+
+    /// Returns the uncompressed chunk. The content must not expand to more than `max_len` bytes
+    /// (e.g. [`Chunking::max_chunk_size`]), otherwise `FileError::Encoding` is returned.
+    pub fn decompress(&self, max_len: usize) -> Result<Chunk, FileError> {
+        Ok(Chunk {
+            data: self.encoding.decompress(&self.content.data, max_len)?,
+            leaf_idx: self.content.leaf_idx,
+        })
+    }
+}
+
+/// A collection of files that are served by their roots.
+///
+/// The chunks of in-memory files are moved to a [`ChunkStore`] that is shared by all files of the
+/// repo, so identical chunks are kept once. Files that are read from disk or memory mapped keep
+/// their own storage.
+///
+/// A repo that is opened with [`FileRepo::open`] is kept on disk as well.
+#[derive(Default)]
+pub struct FileRepo {
+    files: HashMap<String, File>,
+    partials: HashMap<String, PartialFile>,
+    aliases: HashMap<String, RootAlias>,
+    directories: HashMap<String, (Manifest, ManifestTree)>,
+    versions: HashMap<String, FileVersion>,
+    store: ChunkStore,
+    disk: Option<RepoDir>,
+}
+
+impl FileRepo {
+    /// Creates an empty repo whose chunk store compresses chunks with the provided encoding.
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            store: ChunkStore::new(compression),
+            ..Self::default()
+        }
+    }
+
+    /// Opens a repo that is kept in the directory at `path`, or creates an empty one.
+    ///
+    /// Files, versions and aliases are written to the directory when they're added and
+    /// restored from it without hashing any chunk: the leaf hashes of the merkle trees are
+    /// stored and the chunks are read from the directory, where files share identical chunks,
+    /// or from the file on disk a tree was built from. Entries that weren't completely written
+    /// before a crash are dropped, a file on disk that was modified since isn't served until it's
+    /// restored. The manifests of directories are stored as well, a directory is dropped if one
+    /// of its files is. Partial files are kept in memory only.
+    ///
+    /// # Examples:
+    /// ```
+    /// use pmtorrent::{Compression, File, FileRepo};
+    ///
+    /// let dir = tempfile::tempdir().unwrap();
+    /// let file = File::new(&[7u8; 5000]).unwrap();
+    /// let hash = file.get_root().unwrap().to_hex();
+    ///
+    /// let mut repo = FileRepo::open(dir.path(), Compression::None).unwrap();
+    /// repo.add(file).unwrap();
+    /// drop(repo);
+    ///
+    /// let repo = FileRepo::open(dir.path(), Compression::None).unwrap();
+    /// let piece = repo.get_piece(hash, 4).unwrap();
+    /// assert_eq!(piece.content.data, vec![7u8; 904]);
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P, compression: Compression) -> Result<Self, RepoError> {
+        let (disk, contents) = RepoDir::open(path.as_ref())?;
+        let mut repo = Self::new(compression);
+        for file in contents.files {
+            repo.files.insert(file.get_root()?.to_hex(), file);
+        }
+        for version in contents.versions {
+            repo.versions.insert(version.root.to_hex(), version);
+        }
+        for alias in contents.aliases {
+            repo.aliases.insert(alias.old.to_hex(), alias);
+        }
+        for manifest in contents.directories {
+            let tree = ManifestTree::new(&manifest)?;
+            repo.directories
+                .insert(tree.root()?.to_hex(), (manifest, tree));
+        }
+
+        repo.disk = Some(disk);
+        Ok(repo)
+    }
+
+    pub fn add(&mut self, mut file: File) -> Result<(), RepoError> {
+        let hash = file.get_root()?.to_hex();
+        if let Some(disk) = self.disk.as_mut() {
+            disk.add(&file)?;
+        }
+        self.store_chunks(&mut file)?;
+
+        if let Some(replaced) = self.files.insert(hash, file) {
+            self.release_chunks(&replaced);
+        }
+        self.save_index()
+    }
+
+    /// Adds all files of a directory, they're served by their own roots as well as by the path
+    /// in the directory, see [`FileRepo::get_directory_piece`].
+    pub fn add_directory(&mut self, directory: Directory) -> Result<(), RepoError> {
+        let hash = directory.root()?.to_hex();
+        let (manifest, tree, files) = directory.into_parts();
+        for file in files {
+            self.add(file)?;
+        }
+
+        self.directories.insert(hash, (manifest, tree));
+        self.save_index()
+    }
+
+    /// Roots of all directories added with [`FileRepo::add_directory`].
+    pub fn get_directories(&self) -> Vec<String> {
+        self.directories.keys().cloned().collect()
+    }
+
+    pub fn get_manifest(&self, hash: &str) -> Option<&Manifest> {
+        self.directories.get(hash).map(|(manifest, _)| manifest)
+    }
+
+    /// Returns a piece of the file at `path` of a directory together with the proof that the
+    /// path maps to the file root.
+    pub fn get_directory_piece(
+        &self,
+        hash: &str,
+        path: &str,
+        piece: usize,
+    ) -> Result<DirectoryPiece, RepoError> {
+        let (manifest, tree) = self.directories.get(hash).ok_or(RepoError::DoesntExist)?;
+        let entry_idx = manifest.find(path).ok_or(RepoError::DoesntExist)?;
+        let entry = manifest.entries[entry_idx].clone();
+        let piece = self.get_piece(entry.root.to_hex(), piece)?;
+
+        Ok(DirectoryPiece {
+            entry,
+            entry_idx,
+            entry_proof: tree.entry_proof(entry_idx)?,
+            piece,
+        })
+    }
+
+    /// Adds a new version of the file with the `previous` root. The unchanged chunks are shared
+    /// with the previous version in the chunk store, so only the changed ones take up space.
+    ///
+    /// Returns `RepoError::VersionExists` if the root of the file is `previous` or already has a
+    /// version, so the history can't contain a cycle.
+    pub fn add_version(&mut self, previous: &str, file: File) -> Result<FileVersion, RepoError> {
+        let base_file = self.files.get(previous).ok_or(RepoError::DoesntExist)?;
+        let root = file.get_root()?;
+        let hash = root.to_hex();
+        if hash == previous || self.versions.contains_key(&hash) {
+            return Err(RepoError::VersionExists);
+        }
+
+        let changed = base_file.changed_leaves(&file)?;
+        let base = match self.versions.get(previous) {
+            Some(base) => base.clone(),
+            None => FileVersion {
+                root: base_file.get_root()?,
+                previous: None,
+                version: 0,
+                changed: vec![],
+            },
+        };
+        let version = FileVersion {
+            root,
+            previous: Some(base.root.clone()),
+            version: base.version + 1,
+            changed,
+        };
+        self.add(file)?;
+        self.versions.entry(previous.to_string()).or_insert(base);
+        self.versions.insert(hash, version.clone());
+        self.save_index()?;
+        Ok(version)
+    }
+
+    /// Builds a new version from a delta to a stored file, see [`FileDelta::apply`].
+    pub fn add_delta(&mut self, delta: &FileDelta) -> Result<FileVersion, RepoError> {
+        let previous = delta.base.to_hex();
+        let base = self.files.get(&previous).ok_or(RepoError::DoesntExist)?;
+        let file = delta.apply(base)?;
+        self.add_version(&previous, file)
+    }
+
+    /// Returns the delta from the file with the `old` root to the one with the `new` root.
+    pub fn get_delta(&self, old: &str, new: &str) -> Result<FileDelta, RepoError> {
+        let old = self.files.get(old).ok_or(RepoError::DoesntExist)?;
+        let new = self.files.get(new).ok_or(RepoError::DoesntExist)?;
+        Ok(FileDelta::between(old, new)?)
+    }
+
+    /// Leaves that differ between two stored files, e.g. two versions that aren't adjacent.
+    pub fn changed_leaves(&self, old: &str, new: &str) -> Result<Vec<usize>, RepoError> {
+        let old = self.files.get(old).ok_or(RepoError::DoesntExist)?;
+        let new = self.files.get(new).ok_or(RepoError::DoesntExist)?;
+        Ok(old.changed_leaves(new)?)
+    }
+
+    /// The versions up to the one with the provided root, the first version comes first. It's
+    /// empty for a file without versions.
+    ///
+    /// Versions are kept when their files are removed, so the history can still be listed.
+    pub fn get_versions(&self, hash: &str) -> Vec<FileVersion> {
+        let mut versions = Vec::new();
+        let mut next = self.versions.get(hash);
+        while let Some(version) = next {
+            versions.push(version.clone());
+            next = version
+                .previous
+                .as_ref()
+                .and_then(|previous| self.versions.get(&previous.to_hex()));
+        }
+
+        versions.reverse();
+        versions
+    }
+
+    /// Removes a file and drops its chunks from the chunk store unless another file shares them.
+    pub fn remove(&mut self, hash: &str) -> Result<(), RepoError> {
+        let file = self.files.remove(hash).ok_or(RepoError::DoesntExist)?;
+        self.release_chunks(&file);
+        if let Some(disk) = self.disk.as_mut() {
+            disk.remove(hash);
+        }
+        self.save_index()
+    }
+
+    /// Statistics of the chunk store, e.g. how many bytes are saved by deduplication.
+    pub fn store_stats(&self) -> StoreStats {
+        self.store.stats()
+    }
+
+    /// Moves the chunks of an in-memory file to the chunk store, or adds references to them if
+    /// they're already there (e.g. for a rehashed file).
+    fn store_chunks(&self, file: &mut File) -> Result<(), FileError> {
+        let stored = match file.storage() {
+            ChunkStorage::Disk(_) | ChunkStorage::Mmap(_) | ChunkStorage::Persisted(_) => {
+                return Ok(())
+            }
+            ChunkStorage::Stored(stored) if stored.store.same_store(&self.store) => {
+                return self.store.retain(&stored.keys);
+            }
+            storage => {
+                let keys = (0..storage.len())
+                    .map(|idx| Ok(self.store.insert(&storage.get(idx)?.data)))
+                    .collect::<Result<Vec<_>, FileError>>()?;
+                StoredChunks {
+                    store: self.store.clone(),
+                    keys: Arc::new(keys),
+                }
+            }
+        };
+
+        file.set_storage(ChunkStorage::Stored(stored));
+        Ok(())
+    }
+
+    /// Writes the index of a repo that is kept on disk.
+    fn save_index(&self) -> Result<(), RepoError> {
+        if let Some(disk) = &self.disk {
+            disk.save_index(
+                self.versions.values(),
+                self.aliases.values(),
+                self.directories.values().map(|(manifest, _)| manifest),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn release_chunks(&self, file: &File) {
+        if let ChunkStorage::Stored(stored) = file.storage() {
+            if stored.store.same_store(&self.store) {
+                self.store.release(&stored.keys);
+            }
+        }
+    }
+
+    /// Adds a file that is being downloaded, it is listed with its live bitfield, but its pieces
+    /// can't be served as there is no merkle tree to prove them.
+    pub fn add_partial(&mut self, partial: PartialFile) {
+        self.partials.insert(partial.root().to_hex(), partial);
+    }
+
+    pub fn get_partial_mut(&mut self, hash: &str) -> Option<&mut PartialFile> {
+        self.partials.get_mut(hash)
+    }
+
+    pub fn remove_partial(&mut self, hash: &str) -> Option<PartialFile> {
+        self.partials.remove(hash)
+    }
+
+    /// Describes every stored and partial file. Fails if the chunk lengths of a stored file
+    /// can't be read.
+    pub fn get_available(&self) -> Result<Vec<FileDescription>, RepoError> {
+        let files = self.files.iter().map(|(h, f)| {
+            Ok(FileDescription {
+                hash: h.clone(),
+                hash_algorithm: f.algorithm(),
+                size: f.byte_len(),
+                chunking: f.chunking(),
+                chunk_size: f.chunk_size(),
+                pieces: f.get_size(),
+                chunk_lengths: f.chunk_lengths()?,
+                bitfield: f.bitfield(),
+                randomart: f.fingerprint(),
+                replaced_by: self.aliases.get(h).map(|a| a.new.to_hex()),
+                previous: self
+                    .versions
+                    .get(h)
+                    .and_then(|v| v.previous.as_ref())
+                    .map(|p| p.to_hex()),
+            })
+        });
+
+        let partials = self.partials.iter().map(|(h, p)| {
+            Ok(FileDescription {
+                hash: h.clone(),
+                hash_algorithm: p.root().algorithm(),
+                size: p.byte_len(),
+                chunking: p.chunking(),
+                chunk_size: p.chunk_size(),
+                pieces: p.chunk_count(),
+                chunk_lengths: p.chunk_lengths(),
+                bitfield: p.bitfield().clone(),
+                randomart: p.root().randomart(),
+                replaced_by: None,
+                previous: None,
+            })
+        });
+
+        files.chain(partials).collect()
+    }
+
+    pub fn get_piece(&self, hash: String, piece: usize) -> Result<Piece, RepoError> {
+        let file = self.files.get(&hash).ok_or(RepoError::DoesntExist)?;
+        let (content, proof) = file.get_chunk(piece)?;
+        Ok(Piece {
+            content,
+            proof,
+            encoding: Compression::None,
+        })
+    }
+
+    /// Returns a block of a piece, see [`BlockPiece`].
+    pub fn get_block(
+        &self,
+        hash: &str,
+        piece: usize,
+        block: usize,
+    ) -> Result<BlockPiece, RepoError> {
+        let file = self.files.get(hash).ok_or(RepoError::DoesntExist)?;
+        Ok(file.get_block(piece, block)?)
+    }
+
+    /// Reads a span of bytes of a file with a range proof, see [`File::read_at`].
+    pub fn read_at(&self, hash: &str, offset: u64, len: usize) -> Result<RangePiece, RepoError> {
+        let file = self.files.get(hash).ok_or(RepoError::DoesntExist)?;
+        Ok(file.read_at(offset, len)?)
+    }
+
+    /// Rehashes every stored file that doesn't use the provided algorithm yet and records an
+    /// alias from the old root to the new one, signed by `signer`.
+    ///
+    /// Old files are kept and served under their old roots until [`FileRepo::finish_migration`]
+    /// is called, so clients that hold the old roots can still download and verify pieces.
+    pub fn migrate(
+        &mut self,
+        algorithm: HashAlgorithm,
+        signer: &Ed25519KeyPair,
+    ) -> Result<Vec<RootAlias>, RepoError> {
+        let mut migrated = Vec::new();
+
+        for (hash, file) in self.files.iter() {
+            if file.algorithm() == algorithm || self.aliases.contains_key(hash) {
+                continue;
+            }
+
+            let new_file = file.rehash(algorithm)?;
+            let alias = RootAlias::new(file.get_root()?, new_file.get_root()?, signer);
+            migrated.push((alias, new_file));
+        }
+
+        let mut aliases = Vec::with_capacity(migrated.len());
+        for (alias, new_file) in migrated {
+            self.add(new_file)?;
+            self.aliases.insert(alias.old.to_hex(), alias.clone());
+            aliases.push(alias);
+        }
+        self.save_index()?;
+
+        Ok(aliases)
+    }
+
+    /// Returns the alias recorded for an old root by [`FileRepo::migrate`].
+    pub fn get_alias(&self, hash: &str) -> Option<&RootAlias> {
+        self.aliases.get(hash)
+    }
+
+    /// Ends the transition period by removing the files that are served under their old roots.
+    /// The aliases are kept, so clients can still look up the new root of an old one.
+    pub fn finish_migration(&mut self) -> Result<(), RepoError> {
+        let old: Vec<String> = self.aliases.keys().cloned().collect();
+        for hash in old {
+            // The old file could have been removed already.
+            match self.remove(&hash) {
+                Ok(()) | Err(RepoError::DoesntExist) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_files_with_different_hashes() {
+        use super::*;
+
+        let data = [3u8; 2048];
+        let mut repo = FileRepo::default();
+        repo.add(File::new(&data).unwrap()).unwrap();
+        repo.add(File::new_with_hash(&data, HashAlgorithm::Sha384).unwrap())
+            .unwrap();
+
+        let available = repo.get_available().unwrap();
+        assert_eq!(available.len(), 2);
+
+        for description in available {
+            let piece = repo.get_piece(description.hash.clone(), 1).unwrap();
+            assert_eq!(
+                description.hash.len(),
+                description.hash_algorithm.hash_len() * 2
+            );
+            assert_eq!(piece.proof[0].algorithm(), description.hash_algorithm);
+        }
+    }
+
+    #[test]
+    fn test_bitfields_in_description() {
+        use super::*;
+
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let file = File::new(&data).unwrap();
+        let root = file.get_root().unwrap();
+
+        let mut repo = FileRepo::default();
+        repo.add(file).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let partial =
+            PartialFile::create(dir.path().join("out"), root.clone(), 5000, 1024).unwrap();
+        let mut other = FileRepo::default();
+        other.add_partial(partial);
+
+        let full = &repo.get_available().unwrap()[0];
+        assert!(full.bitfield.is_full());
+        assert_eq!(full.bitfield.len(), 5);
+
+        let hash = root.to_hex();
+        let (chunk, proof) = repo.files[&hash].get_chunk(1).unwrap();
+        other
+            .get_partial_mut(&hash)
+            .unwrap()
+            .add_chunk(1, &chunk.data, proof)
+            .unwrap();
+
+        let live = &other.get_available().unwrap()[0];
+        assert_eq!(live.hash, hash);
+        assert_eq!(live.bitfield.iter_ones().collect::<Vec<usize>>(), vec![1]);
+
+        // What the partial file still needs from the full one.
+        let needed = full.bitfield.difference(&live.bitfield);
+        assert_eq!(needed.count_ones(), 4);
+
+        let json = serde_json::to_string(live).unwrap();
+        let parsed: FileDescription = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.bitfield, live.bitfield);
+    }
+
+    #[test]
+    fn test_deduplicated_chunks() {
+        use super::*;
+
+        // Two files that share a zero filled region and the first chunk.
+        let mut first = vec![0u8; 8192];
+        first[..1024].copy_from_slice(&[7u8; 1024]);
+        let mut second = first.clone();
+        second.extend_from_slice(&[9u8; 1024]);
+
+        let mut repo = FileRepo::default();
+        let first = File::new(&first).unwrap();
+        let first_root = first.get_root().unwrap().to_hex();
+        repo.add(first).unwrap();
+        repo.add(File::new_with_hash(&second, HashAlgorithm::Sha384).unwrap())
+            .unwrap();
+
+        let stats = repo.store_stats();
+        assert_eq!(stats.chunks, 3);
+        assert_eq!(stats.references, 17);
+        assert_eq!(stats.stored_bytes, 3 * 1024);
+        assert_eq!(stats.deduplicated_bytes(), 14 * 1024);
+
+        // The pieces are still proven against the trees of both files.
+        for description in repo.get_available().unwrap() {
+            let piece = repo.get_piece(description.hash.clone(), 0).unwrap();
+            assert_eq!(piece.content.data, vec![7u8; 1024]);
+            assert_eq!(piece.content.leaf_idx, 0);
+        }
+
+        repo.remove(&first_root).unwrap();
+        let stats = repo.store_stats();
+        assert_eq!(stats.chunks, 3);
+        assert_eq!(stats.references, 9);
+        assert_eq!(stats.deduplicated_bytes(), 6 * 1024);
+        assert!(matches!(
+            repo.remove(&first_root),
+            Err(RepoError::DoesntExist)
+        ));
+
+        let second_root = repo.get_available().unwrap()[0].hash.clone();
+        repo.remove(&second_root).unwrap();
+        assert_eq!(repo.store_stats(), StoreStats::default());
+    }
+
+    #[test]
+    fn test_directory_pieces() {
+        use super::*;
+
+        let files = vec![
+            ("a.txt".to_string(), File::new(&[1u8; 2000]).unwrap()),
+            ("b/c.txt".to_string(), File::new(&[1u8; 2000]).unwrap()),
+        ];
+        let directory = Directory::from_files(HashAlgorithm::Sha256, files).unwrap();
+        let root = directory.root().unwrap();
+
+        let mut repo = FileRepo::default();
+        repo.add_directory(directory).unwrap();
+        assert_eq!(repo.get_directories(), vec![root.to_hex()]);
+        assert_eq!(
+            repo.get_manifest(&root.to_hex()).unwrap().root().unwrap(),
+            root
+        );
+
+        // Both paths have the same content, so the files share the root and the chunks.
+        assert_eq!(repo.get_available().unwrap().len(), 1);
+        assert_eq!(repo.store_stats().chunks, 2);
+
+        for path in ["a.txt", "b/c.txt"] {
+            let piece = repo.get_directory_piece(&root.to_hex(), path, 1).unwrap();
+            assert!(piece.verify(&root, path, 1).is_ok());
+        }
+        assert!(matches!(
+            repo.get_directory_piece(&root.to_hex(), "missing", 0),
+            Err(RepoError::DoesntExist)
+        ));
+    }
+
+    #[test]
+    fn test_migrate() {
+        use super::*;
+
+        use ring::rand::SystemRandom;
+        use ring::signature::KeyPair;
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        let mut repo = FileRepo::default();
+        let file = File::new(&[5u8; 3000]).unwrap();
+        let old_root = file.get_root().unwrap();
+        repo.add(file).unwrap();
+
+        let aliases = repo.migrate(HashAlgorithm::Sha512, &key).unwrap();
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases[0].old, old_root);
+        assert_eq!(aliases[0].new.algorithm(), HashAlgorithm::Sha512);
+        assert!(aliases[0].verify_signed_by(key.public_key().as_ref()));
+
+        // Both identities are served during the transition period.
+        let old_hash = old_root.to_hex();
+        let new_hash = aliases[0].new.to_hex();
+        let old_piece = repo.get_piece(old_hash.clone(), 2).unwrap();
+        let new_piece = repo.get_piece(new_hash.clone(), 2).unwrap();
+        assert_eq!(old_piece.content.data, new_piece.content.data);
+        assert_eq!(old_piece.proof[0].algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(new_piece.proof[0].algorithm(), HashAlgorithm::Sha512);
+
+        let available = repo.get_available().unwrap();
+        let old = available.iter().find(|d| d.hash == old_hash).unwrap();
+        assert_eq!(old.replaced_by, Some(new_hash.clone()));
+
+        // Migrating again doesn't rehash the files that are already migrated.
+        assert!(repo
+            .migrate(HashAlgorithm::Sha512, &key)
+            .unwrap()
+            .is_empty());
+
+        repo.finish_migration().unwrap();
+        assert!(matches!(
+            repo.get_piece(old_hash.clone(), 2),
+            Err(RepoError::DoesntExist)
+        ));
+        assert!(repo.get_piece(new_hash, 2).is_ok());
+        assert_eq!(repo.get_alias(&old_hash), Some(&aliases[0]));
+    }
+
+    #[test]
+    fn test_version_cycles() {
+        use super::*;
+
+        let first: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut second = first.clone();
+        second[0] = 1;
+
+        let mut repo = FileRepo::default();
+        let first_file = File::new(&first).unwrap();
+        let first_hash = first_file.get_root().unwrap().to_hex();
+        repo.add(first_file).unwrap();
+
+        // A version with the root of its previous version, also from an empty delta.
+        let same = File::new(&first).unwrap();
+        assert!(matches!(
+            repo.add_version(&first_hash, same),
+            Err(RepoError::VersionExists)
+        ));
+        let empty = FileDelta::between(&repo.files[&first_hash], &repo.files[&first_hash]).unwrap();
+        assert!(matches!(
+            repo.add_delta(&empty),
+            Err(RepoError::VersionExists)
+        ));
+        assert!(repo.get_versions(&first_hash).is_empty());
+
+        // Reverting to the first version doesn't make the history a loop.
+        let second = repo
+            .add_version(&first_hash, File::new(&second).unwrap())
+            .unwrap();
+        let second_hash = second.root.to_hex();
+        assert!(matches!(
+            repo.add_version(&second_hash, File::new(&first).unwrap()),
+            Err(RepoError::VersionExists)
+        ));
+        let versions: Vec<usize> = repo
+            .get_versions(&second_hash)
+            .iter()
+            .map(|v| v.version)
+            .collect();
+        assert_eq!(versions, vec![0, 1]);
+        assert_eq!(repo.get_versions(&first_hash).len(), 1);
+    }
+
+    #[test]
+    fn test_versions() {
+        use super::*;
+
+        let first: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let mut second = first.clone();
+        second[2048..2100].fill(0);
+        let mut third = second.clone();
+        third.extend_from_slice(&[1u8; 500]);
+
+        let mut repo = FileRepo::default();
+        let first = File::new(&first).unwrap();
+        let first_hash = first.get_root().unwrap().to_hex();
+        repo.add(first).unwrap();
+        let stored = repo.store_stats().stored_bytes;
+
+        let second = repo
+            .add_version(&first_hash, File::new(&second).unwrap())
+            .unwrap();
+        assert_eq!(second.version, 1);
+        assert_eq!(second.changed, vec![2]);
+        assert_eq!(repo.store_stats().stored_bytes, stored + 1024);
+
+        // The next version arrives as a delta to the previous one.
+        let third = File::new(&third).unwrap();
+        let second_hash = second.root.to_hex();
+        let delta = FileDelta::between(&repo.files[&second_hash], &third).unwrap();
+        let third = repo.add_delta(&delta).unwrap();
+        assert_eq!(third.changed, vec![9, 10]);
+        let third_hash = third.root.to_hex();
+        assert_eq!(
+            repo.get_delta(&second_hash, &third_hash).unwrap().root,
+            third.root
+        );
+
+        let versions = repo.get_versions(&third_hash);
+        let numbers: Vec<usize> = versions.iter().map(|v| v.version).collect();
+        assert_eq!(numbers, vec![0, 1, 2]);
+        assert_eq!(versions[0].previous, None);
+        assert_eq!(versions[2].previous, Some(second.root.clone()));
+        assert_eq!(
+            repo.changed_leaves(&first_hash, &third_hash).unwrap(),
+            vec![2, 9, 10]
+        );
+
+        // Every version is served by its own root.
+        for version in &versions {
+            let hash = version.root.to_hex();
+            assert!(repo.get_piece(hash.clone(), 0).is_ok());
+            let description = repo
+                .get_available()
+                .unwrap()
+                .into_iter()
+                .find(|d| d.hash == hash)
+                .unwrap();
+            assert_eq!(
+                description.previous,
+                version.previous.as_ref().map(|p| p.to_hex())
+            );
+        }
+        assert!(repo.get_versions("missing").is_empty());
+        assert!(matches!(
+            repo.add_version("missing", File::new(&[1u8; 10]).unwrap()),
+            Err(RepoError::DoesntExist)
+        ));
+    }
+}
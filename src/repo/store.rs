@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::{Chunk, Compression, FileError, Hasher, Sha256Hash, Sha256Hasher};
+
+/// A content-addressed store of chunk data that is shared by all files of a
+/// [`crate::FileRepo`].
+///
+/// Chunks are keyed by the Sha256 hash of their data, independently of the hash algorithm or the
+/// padding used by the merkle tree of a file, so identical chunks (e.g. runs of zero bytes) are
+/// stored once no matter how many files or indices share them. Every stored chunk counts the
+/// references to it and is dropped when the last one is released.
+///
+/// A store created with [`ChunkStore::new`] can also keep the chunks compressed, they're
+/// decompressed when they're read. The key is always the hash of the uncompressed data.
+///
+/// Cloning a store returns another handle to the same chunks.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkStore {
+    chunks: Arc<RwLock<HashMap<Sha256Hash, StoredChunk>>>,
+    compression: Compression,
+}
+
+#[derive(Debug)]
+struct StoredChunk {
+    data: Bytes,
+    /// The encoding of `data`, chunks that don't get smaller are stored uncompressed.
+    encoding: Compression,
+    /// Length of the uncompressed data.
+    len: usize,
+    refs: usize,
+}
+
+/// Statistics of a [`ChunkStore`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreStats {
+    /// Number of unique chunks.
+    pub chunks: usize,
+
+    /// Number of chunk references, i.e. the chunks of all files together.
+    pub references: usize,
+
+    /// Bytes that are actually kept in memory.
+    pub stored_bytes: u64,
+
+    /// Bytes of the unique chunks before they're compressed.
+    pub uncompressed_bytes: u64,
+
+    /// Bytes of all files together, as if every chunk was stored separately.
+    pub referenced_bytes: u64,
+}
+
+impl StoreStats {
+    /// Bytes saved by storing identical chunks once.
+    pub fn deduplicated_bytes(&self) -> u64 {
+        self.referenced_bytes - self.uncompressed_bytes
+    }
+
+    /// Bytes saved by compressing the unique chunks.
+    pub fn compressed_bytes(&self) -> u64 {
+        self.uncompressed_bytes - self.stored_bytes
+    }
+}
+
+impl ChunkStore {
+    /// Creates an empty store that compresses new chunks with the provided encoding.
+    pub fn new(compression: Compression) -> Self {
+        Self {
+            compression,
+            ..Self::default()
+        }
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Stores the chunk data (unless an identical chunk is already stored) and adds a reference
+    /// to it. Returns the key the data can be retrieved with.
+    ///
+    /// New data is copied (or compressed), so a stored chunk doesn't keep alive the whole buffer
+    /// it was sliced from.
+    pub fn insert(&self, data: &[u8]) -> Sha256Hash {
+        let key = Sha256Hasher.digest(data);
+        let mut chunks = self.chunks.write().expect("chunk store lock");
+        chunks
+            .entry(key.clone())
+            .or_insert_with(|| {
+                let (stored, encoding) = self.compression.compress_smaller(data);
+                StoredChunk {
+                    data: stored,
+                    encoding,
+                    len: data.len(),
+                    refs: 0,
+                }
+            })
+            .refs += 1;
+
+        key
+    }
+
+    /// Adds a reference to every already stored chunk.
+    pub fn retain(&self, keys: &[Sha256Hash]) -> Result<(), FileError> {
+        let mut chunks = self.chunks.write().expect("chunk store lock");
+        if !keys.iter().all(|k| chunks.contains_key(k)) {
+            return Err(FileError::File);
+        }
+
+        for key in keys {
+            if let Some(chunk) = chunks.get_mut(key) {
+                chunk.refs += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a reference to every chunk and drops the chunks that are no longer referenced.
+    pub fn release(&self, keys: &[Sha256Hash]) {
+        let mut chunks = self.chunks.write().expect("chunk store lock");
+        for key in keys {
+            if let Some(chunk) = chunks.get_mut(key) {
+                chunk.refs -= 1;
+                if chunk.refs == 0 {
+                    chunks.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Returns the uncompressed data of a stored chunk, it's not copied unless the chunk is
+    /// compressed. Fails with `FileError::File` if the chunk isn't stored and with
+    /// `FileError::Encoding` if it can't be decompressed.
+    pub fn get(&self, key: &Sha256Hash) -> Result<Bytes, FileError> {
+        let chunks = self.chunks.read().expect("chunk store lock");
+        let chunk = chunks.get(key).ok_or(FileError::File)?;
+        chunk
+            .encoding
+            .decompress(&chunk.data, chunk.len)
+            .map_err(|_| FileError::Encoding)
+    }
+
+    pub fn stats(&self) -> StoreStats {
+        let chunks = self.chunks.read().expect("chunk store lock");
+        chunks
+            .values()
+            .fold(StoreStats::default(), |mut stats, chunk| {
+                let len = chunk.len as u64;
+                stats.chunks += 1;
+                stats.references += chunk.refs;
+                stats.stored_bytes += chunk.data.len() as u64;
+                stats.uncompressed_bytes += len;
+                stats.referenced_bytes += len * chunk.refs as u64;
+                stats
+            })
+    }
+
+    /// Returns true if both handles point to the same chunks.
+    pub fn same_store(&self, other: &ChunkStore) -> bool {
+        Arc::ptr_eq(&self.chunks, &other.chunks)
+    }
+}
+
+/// The chunks of a single file kept in a [`ChunkStore`].
+#[derive(Clone, Debug)]
+pub(crate) struct StoredChunks {
+    pub store: ChunkStore,
+    pub keys: Arc<Vec<Sha256Hash>>,
+}
+
+impl StoredChunks {
+    pub fn get(&self, idx: usize) -> Result<Chunk, FileError> {
+        let key = self.keys.get(idx).ok_or(FileError::File)?;
+        let data = self.store.get(key)?;
+
+        Ok(Chunk {
+            data,
+            leaf_idx: idx,
+        })
+    }
+}
+
+mod tests {
+    #[test]
+    fn test_chunk_store() {
+        use super::*;
+
+        let store = ChunkStore::default();
+        let zeros = store.insert(&[0u8; 1024]);
+        let other = store.insert(&[1u8; 512]);
+        assert_eq!(store.insert(&[0u8; 1024]), zeros);
+
+        let stats = store.stats();
+        assert_eq!(stats.chunks, 2);
+        assert_eq!(stats.references, 3);
+        assert_eq!(stats.stored_bytes, 1536);
+        assert_eq!(stats.deduplicated_bytes(), 1024);
+
+        store.release(&[zeros.clone(), other.clone()]);
+        assert!(matches!(store.get(&other), Err(FileError::File)));
+        assert_eq!(store.get(&zeros).unwrap().len(), 1024);
+
+        assert!(store.retain(&[zeros.clone(), other]).is_err());
+        store.release(&[zeros]);
+        assert_eq!(store.stats(), StoreStats::default());
+    }
+
+    #[test]
+    fn test_compressed_store() {
+        use super::*;
+        use crate::test_data;
+
+        let store = ChunkStore::new(Compression::Deflate);
+        let text = b"pmtorrent ".repeat(100);
+        let compressible = store.insert(&text);
+        let random = test_data(512);
+        let incompressible = store.insert(&random);
+        store.insert(&text);
+
+        assert_eq!(store.get(&compressible).unwrap(), text);
+        assert_eq!(store.get(&incompressible).unwrap(), random);
+
+        let stats = store.stats();
+        assert_eq!(stats.uncompressed_bytes, 1512);
+        assert_eq!(stats.deduplicated_bytes(), 1000);
+        assert!(stats.stored_bytes < 1000);
+        assert_eq!(stats.compressed_bytes(), 1512 - stats.stored_bytes);
+    }
+}